@@ -0,0 +1,70 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use dumbledore::component::Component;
+use dumbledore::world::World;
+use dumbledore::{Bundle, Component};
+
+const ENTITIES: u32 = 10_000;
+
+#[derive(Clone, Component)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Clone, Component)]
+pub struct Velocity {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Clone, Bundle)]
+pub struct Body {
+    pub position: Position,
+    pub velocity: Velocity,
+}
+
+fn world() -> World {
+    let mut world = World::new(ENTITIES);
+    world
+        .spawn_batch((0..ENTITIES).map(|i| Body {
+            position: Position {
+                x: i as f32,
+                y: 0.0,
+            },
+            velocity: Velocity { x: 1.0, y: 0.5 },
+        }))
+        .unwrap();
+    world
+}
+
+fn integrate(c: &mut Criterion) {
+    let world = world();
+    let mut group = c.benchmark_group("integrate 10k");
+    group.bench_function("per entity locks", |b| {
+        b.iter(|| {
+            world
+                .query::<(Position, Velocity)>()
+                .for_each(|_, (mut position, velocity)| {
+                    let velocity = velocity.as_ref();
+                    let position = position.as_mut();
+                    position.x += velocity.x;
+                    position.y += velocity.y;
+                })
+        })
+    });
+    group.bench_function("column locks", |b| {
+        b.iter(|| {
+            world
+                .query::<(Position, Velocity)>()
+                .columns_mut(|_, (position, velocity)| {
+                    position.x += velocity.x;
+                    position.y += velocity.y;
+                })
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, integrate);
+criterion_main!(benches);
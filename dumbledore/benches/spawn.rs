@@ -0,0 +1,102 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use dumbledore::component::Component;
+use dumbledore::world::World;
+use dumbledore::{Bundle, Component};
+
+const MOBS: u32 = 10_000;
+
+#[derive(Clone, Component)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Clone, Component)]
+pub struct Health {
+    pub health: f32,
+}
+
+#[derive(Clone, Bundle)]
+pub struct Mob {
+    pub position: Position,
+    pub health: Health,
+}
+
+fn mob(i: u32) -> Mob {
+    Mob {
+        position: Position {
+            x: i as f32,
+            y: 0.0,
+        },
+        health: Health { health: 20.0 },
+    }
+}
+
+/// A World sized for the wave, like a server that has seen it before.
+fn world() -> World {
+    let mut world = World::new(MOBS);
+    world.add_archetype::<Mob>(MOBS as usize);
+    world
+}
+
+fn spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn 10k");
+    group.bench_function("add_entity", |b| {
+        b.iter_batched(
+            world,
+            |world| {
+                for i in 0..MOBS {
+                    world.add_entity(mob(i)).unwrap();
+                }
+                world
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("spawn_batch", |b| {
+        b.iter_batched(
+            world,
+            |mut world| {
+                world.spawn_batch((0..MOBS).map(mob)).unwrap();
+                world
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn despawn(c: &mut Criterion) {
+    let setup = || {
+        let mut world = world();
+        let entities = world.spawn_batch((0..MOBS).map(mob)).unwrap();
+        (world, entities)
+    };
+    let mut group = c.benchmark_group("despawn 10k");
+    group.bench_function("remove_entity", |b| {
+        b.iter_batched(
+            setup,
+            |(mut world, entities)| {
+                for entity in entities {
+                    world.remove_entity(entity.id).unwrap();
+                }
+                world
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("despawn_batch", |b| {
+        b.iter_batched(
+            setup,
+            |(mut world, entities)| {
+                world.despawn_batch(&entities).unwrap();
+                world
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, spawn, despawn);
+criterion_main!(benches);
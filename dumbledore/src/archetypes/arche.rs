@@ -1,17 +1,22 @@
 use std::alloc::{alloc, dealloc, Layout};
 
 use std::fmt::Debug;
+use std::panic::Location;
 use std::{mem, ptr};
 
 use crate::archetypes::ComponentInfo;
-use crate::component::{Bundle, ComponentLookup};
+use crate::component::{Bundle, Component, ComponentLookup};
+use crate::component_ref::{
+    OwnedComponentRef, OwnedMutComponentRef, RawComponentRef, RawMutComponentRef,
+};
+use std::any::TypeId;
 
+use crate::archetypes::borrow::{self, AtomicU8, Borrow, BorrowPolicy};
 use crate::sets::TypeIdSet;
-use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicU8, Ordering};
-use std::sync::{Arc, Mutex};
+use crate::world::WorldError;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-/// Contains a Slice of AtomicU8s being the RwLock status of each component within the entity.
-///
 /// Contains a pointer to the actual component data. Data is offset by the size of the component.
 #[derive(Debug)]
 pub(crate) struct EntityData {
@@ -20,8 +25,8 @@ pub(crate) struct EntityData {
     pub(crate) entity_id: AtomicU32,
     // If true the entire entity is locked.
     pub(crate) locked: AtomicU8,
-    /// Component Data Locks
-    pub(crate) anti_racey_bytes: Box<[Arc<AtomicU8>]>,
+    /// The change tick each component was last mutably borrowed or added at.
+    pub(crate) changed_ticks: Box<[AtomicU32]>,
 }
 
 impl EntityData {
@@ -32,27 +37,121 @@ impl EntityData {
         self.locked.load(Ordering::Relaxed) == 1
     }
     pub fn is_unlocked(&self) -> bool {
-        self.locked.load(Ordering::Relaxed) == 0
+        self.locked.load(Ordering::SeqCst) == 0
     }
-    pub fn mark_locking(&self) {
-        self.locked.store(1, Ordering::Relaxed);
+    /// Stops new borrows of the entity. Fails if the entity is already locked or someone else is locking it,
+    /// so only the caller that set the mark clears it again.
+    #[must_use]
+    pub fn mark_locking(&self) -> bool {
+        self.locked
+            .compare_exchange(0, 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok()
     }
-    /// Only Will Lock if all components are unlocked.
-    pub fn try_mark_locked(&self) -> bool {
-        for x in self.anti_racey_bytes.iter() {
-            if x.load(Ordering::Relaxed) != 0 {
+    /// Only Will Lock if all components are unlocked and the entity was marked as locking.
+    ///
+    /// `borrows` is the borrow state of this entity. See [ArchetypeInner::borrows]
+    pub fn try_mark_locked(&self, borrows: &[AtomicU8]) -> bool {
+        for x in borrows.iter() {
+            if !borrow::is_unborrowed(x.load(Ordering::SeqCst)) {
                 return false;
             }
         }
-        self.locked.store(2, Ordering::Relaxed);
-        true
+        self.locked
+            .compare_exchange(1, 2, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok()
+    }
+    /// Undoes [EntityData::mark_locking] unless the entity got locked in the meantime.
+    pub fn cancel_locking(&self) {
+        let _ = self
+            .locked
+            .compare_exchange(1, 0, Ordering::Release, Ordering::Relaxed);
     }
     pub fn mark_unlocked(&self) {
-        self.locked.store(0, Ordering::Relaxed);
+        self.locked.store(0, Ordering::Release);
+    }
+}
+
+/// Entities locked by [Archetype::lock_for_removal]. Unlocked again if dropped without being applied.
+///
+/// Holds the free list lock, so no entity can be added to the Archetype in the meantime.
+pub(crate) struct RowRemoval<'a> {
+    archetype: &'a Archetype,
+    free_list: MutexGuard<'a, Vec<u32>>,
+    /// Fill the removed slots with the last entities instead of pushing them to the free list.
+    dense: bool,
+    len: u32,
+    /// Sorted and deduplicated.
+    indices: Vec<u32>,
+    /// The rows moved into the removed slots of a dense Archetype, as (from, to).
+    moves: Vec<(u32, u32)>,
+    locked: Vec<u32>,
+}
+
+impl RowRemoval<'_> {
+    /// Drops the components of the entities.
+    ///
+    /// # Returns
+    /// The ID and new index of every Entity that was moved.
+    pub(crate) fn apply(mut self) -> Vec<(u32, u32)> {
+        let inner = &self.archetype.0;
+        for index in self.indices.iter() {
+            let data = &inner.entity_data[*index as usize];
+            self.archetype.drop_components(data);
+            data.entity_id.store(0, Ordering::Relaxed);
+        }
+        let moved = self
+            .moves
+            .iter()
+            .map(|(from, to)| {
+                let from = &inner.entity_data[*from as usize];
+                let id = self
+                    .archetype
+                    .move_row(from, &inner.entity_data[*to as usize]);
+                (id, *to)
+            })
+            .collect();
+        if self.dense {
+            let len = self.len - self.indices.len() as u32;
+            inner.entities_len.store(len, Ordering::Relaxed);
+        } else {
+            let indices = mem::take(&mut self.indices);
+            self.free_list.extend(indices);
+        }
+        moved
+    }
+}
+
+impl Drop for RowRemoval<'_> {
+    fn drop(&mut self) {
+        for index in self.locked.iter() {
+            self.archetype.0.entity_data[*index as usize].mark_unlocked();
+        }
     }
 }
 
 ///
+/// The state of a column lock held by a writer. Any other value is the number of readers.
+const COLUMN_WRITE: u32 = u32::MAX;
+
+/// A read or write lock on every instance of one component in an Archetype. Released on drop.
+///
+/// Taken with [Archetype::lock_column]. While it is held, per entity borrows that conflict with it fail.
+#[derive(Debug)]
+pub(crate) struct ColumnGuard<'a> {
+    state: &'a AtomicU32,
+    write: bool,
+}
+
+impl Drop for ColumnGuard<'_> {
+    fn drop(&mut self) {
+        if self.write {
+            self.state.store(0, Ordering::Release);
+        } else {
+            self.state.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
 /// This is a Wrapper around a Arc<ArchetypeInner>
 ///
 ///
@@ -83,14 +182,7 @@ impl Archetype {
     /// Ok(Self) if the internal arc was able to be released.
     /// Err(Self) if the internal arc was unable to be released.
     pub fn resize(self, increase_by: Option<usize>) -> Result<Self, Self> {
-        let mut safe = true;
-        for x in self.0.entity_data.iter() {
-            x.mark_locking();
-            if !x.try_mark_locked() {
-                safe = false;
-            }
-        }
-        if !safe {
+        if !self.lock_all() {
             // The entity needs to be completely locked before it can be resized.
             return Err(self);
         }
@@ -104,7 +196,64 @@ impl Archetype {
                 }
                 Ok(Archetype(Arc::new(inner)))
             }
-            Err(error) => Err(Archetype(error)),
+            Err(error) => {
+                for x in error.entity_data.iter() {
+                    x.mark_unlocked();
+                }
+                Err(Archetype(error))
+            }
+        }
+    }
+    /// Attempts to release the internal ArchetypeInner then shrink it to the live entities.
+    ///
+    /// Live entities are moved to the front, so the caller has to update their locations with [ShrinkReport::moved].
+    ///
+    /// # Returns
+    /// Ok if the internal arc was able to be released.
+    /// Err(Self) if the internal arc was unable to be released or an entity is borrowed.
+    pub fn shrink(self) -> Result<(Self, ShrinkReport), Self> {
+        if !self.lock_all() {
+            return Err(self);
+        }
+        match Arc::try_unwrap(self.0) {
+            Ok(value) => {
+                let before = value.bytes_allocated();
+                let (inner, moved) = ArchetypeInner::new_compacted(value);
+                for x in inner.entity_data.iter() {
+                    x.mark_unlocked();
+                }
+                let report = ShrinkReport {
+                    bytes_reclaimed: before - inner.bytes_allocated(),
+                    moved,
+                };
+                Ok((Archetype(Arc::new(inner)), report))
+            }
+            Err(error) => {
+                for x in error.entity_data.iter() {
+                    x.mark_unlocked();
+                }
+                Err(Archetype(error))
+            }
+        }
+    }
+    /// Locks every entity. If one can not be locked, the ones locked so far are unlocked again.
+    fn lock_all(&self) -> bool {
+        for (index, x) in self.0.entity_data.iter().enumerate() {
+            if !x.mark_locking() {
+                self.unlock_first(index);
+                return false;
+            }
+            if !x.try_mark_locked(self.0.borrows(index)) {
+                x.cancel_locking();
+                self.unlock_first(index);
+                return false;
+            }
+        }
+        true
+    }
+    fn unlock_first(&self, count: usize) {
+        for x in self.0.entity_data[..count].iter() {
+            x.mark_unlocked();
         }
     }
     /// Adds an Entity to the Archetype.
@@ -112,73 +261,249 @@ impl Archetype {
     /// # Returns
     /// The index for the Entity in the Archetype.
     pub fn add_entity<Data: Bundle>(&self, entity_id: u32, comps: Data) -> u32 {
+        let (id, ptr) = self.alloc_slot(entity_id);
+        unsafe {
+            comps.put_self(|data, info| self.write_component(ptr, data, &info));
+        }
+
+        id
+    }
+    /// Claims a free slot for the Entity.
+    ///
+    /// # Returns
+    /// The index for the Entity in the Archetype and the pointer to its component data.
+    pub(crate) fn alloc_slot(&self, entity_id: u32) -> (u32, *mut u8) {
+        // The lock is held while the length grows so a swap remove can not move the last entity at the same time
         let mut result = self.0.free_list.lock().unwrap();
         let id = if let Some(pop) = result.pop() {
-            drop(result);
             pop
         } else {
-            drop(result);
-
             self.0.entities_len.fetch_add(1, Ordering::Relaxed)
         };
+        drop(result);
+        (id, self.init_slot(id, entity_id))
+    }
+    /// Claims `count` slots, taking the free list lock once.
+    ///
+    /// The slots have to be set up with [Archetype::init_slot] before components are written to them.
+    pub(crate) fn alloc_slots(&self, count: usize) -> Vec<u32> {
+        let mut free_list = self.0.free_list.lock().unwrap();
+        let reused = free_list.len().min(count);
+        let at = free_list.len() - reused;
+        let mut slots = free_list.split_off(at);
+        let remaining = (count - reused) as u32;
+        let start = self.0.entities_len.fetch_add(remaining, Ordering::Relaxed);
+        drop(free_list);
+        slots.extend(start..start + remaining);
+        slots
+    }
+    /// Assigns the slot to the Entity and marks its components as changed.
+    ///
+    /// # Returns
+    /// The pointer to the component data of the slot.
+    pub(crate) fn init_slot(&self, id: u32, entity_id: u32) -> *mut u8 {
         let data = &self.0.entity_data[id as usize];
         data.entity_id.store(entity_id, Ordering::Relaxed);
-        let ptr = data.inner_ptrs.load(Ordering::Relaxed);
-        unsafe {
-            comps.put_self(|data, info| {
-                let (offset, _index) = *self
-                    .0
-                    .component_offsets
-                    .get(&info.id)
-                    .ok_or_else(|| {
-                        panic!(
-                            "Tried to add a component to an archetype that does not contain it {:?}",
-                            info
-                        )
-                    })
-                    .unwrap();
-                let x = ptr.add(offset as usize);
-                ptr::copy(data, x, info.layout.size());
-            });
+        let tick = self.0.change_tick.load(Ordering::Relaxed);
+        for changed in data.changed_ticks.iter() {
+            changed.store(tick, Ordering::Relaxed);
         }
-
-        id
+        data.inner_ptrs.load(Ordering::Relaxed)
     }
-    /// Returns Err(()) if the entity is locked. However, this does mark the entity as locking so data can not be read anymore
-    #[allow(clippy::result_unit_err)]
-    pub fn remove(&self, index: u32) -> Result<(), ()> {
-        let data = &self.0.entity_data[index as usize];
-        data.mark_locking();
-        if !data.try_mark_locked() {
-            return Err(());
-        }
+    /// Moves the component at `data` into the entity data at `entity_ptr`.
+    ///
+    /// # Safety
+    /// `entity_ptr` must come from [Archetype::alloc_slot] or [Archetype::init_slot] and `data` must point to a valid component described by `info`.
+    pub(crate) unsafe fn write_component(
+        &self,
+        entity_ptr: *mut u8,
+        data: *mut u8,
+        info: &ComponentInfo,
+    ) {
+        let (offset, _index) = *self
+            .0
+            .component_offsets
+            .get(&info.id)
+            .ok_or_else(|| {
+                panic!(
+                    "Tried to add a component to an archetype that does not contain it {:?}",
+                    info
+                )
+            })
+            .unwrap();
+        let x = entity_ptr.add(offset);
+        ptr::copy(data, x, info.layout.size());
+    }
+    /// Drops the entity and leaves its slot on the free list, even in a dense Archetype.
+    ///
+    /// Returns Err(()) if the entity is locked, borrowed or already removed. The entity is left as it was in that case.
+    pub(crate) fn remove(&self, index: u32) -> Result<(), ()> {
+        self.lock_rows(&[index], false).map_err(|_| ())?.apply();
+        Ok(())
+    }
+    fn drop_components(&self, data: &EntityData) {
         let ptr = data.inner_ptrs.load(Ordering::Relaxed);
 
         for comp in self.0.components.iter() {
             let (offset, _) = *self.0.component_offsets.get(&comp.id).unwrap();
             unsafe {
-                let x1 = ptr.add(offset as usize);
+                let x1 = ptr.add(offset);
                 (comp.drop)(x1);
             }
         }
-        data.entity_id.store(0, Ordering::Relaxed);
-        let mut result = self.0.free_list.lock().unwrap();
-        result.push(index);
-        data.mark_unlocked();
-        Ok(())
+    }
+    /// The components stored in this Archetype, sorted by TypeId.
+    pub fn components(&self) -> &[ComponentInfo] {
+        &self.0.components
+    }
+    /// The number of entities that fit without resizing.
+    pub fn capacity(&self) -> usize {
+        self.0.entity_data.len()
+    }
+    /// The number of live entities.
+    pub fn len(&self) -> usize {
+        let free_list = self.0.free_list.lock().unwrap();
+        self.0.entities_len.load(Ordering::Relaxed) as usize - free_list.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The number of removed slots waiting to be reused.
+    pub fn free_list_len(&self) -> usize {
+        self.0.free_list.lock().unwrap().len()
+    }
+    /// The bytes allocated for component data and the per entity bookkeeping.
+    pub fn bytes_allocated(&self) -> usize {
+        self.0.bytes_allocated()
+    }
+    /// True if removed entities are replaced by the last entity instead of leaving a hole.
+    ///
+    /// Every index below the length of a dense Archetype holds a live entity.
+    pub fn is_dense(&self) -> bool {
+        self.0.dense
+    }
+    /// Removes the entity by moving the last entity into its slot, so `0..len` stays filled.
+    ///
+    /// The caller is responsible for updating the location of the moved entity.
+    ///
+    /// # Returns
+    /// Ok with the ID of the Entity that was moved to `index`, None if `index` was the last entity.
+    /// Err(()) if either entity is borrowed, `index` was already removed or the Archetype has removed slots.
+    pub(crate) fn swap_remove(&self, index: u32) -> Result<Option<u32>, ()> {
+        let removal = self.lock_rows(&[index], true).map_err(|_| ())?;
+        if !removal.free_list.is_empty() {
+            return Err(());
+        }
+        Ok(removal.apply().first().map(|(moved, _)| *moved))
+    }
+    /// Moves the components of `from` into the dropped row `to`.
+    ///
+    /// # Returns
+    /// The ID of the moved Entity.
+    fn move_row(&self, from: &EntityData, to: &EntityData) -> u32 {
+        let row_size = self
+            .0
+            .components
+            .iter()
+            .map(|c| c.layout.size())
+            .sum::<usize>();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                from.inner_ptrs.load(Ordering::Relaxed),
+                to.inner_ptrs.load(Ordering::Relaxed),
+                row_size,
+            );
+        }
+        for (to, from) in to.changed_ticks.iter().zip(from.changed_ticks.iter()) {
+            to.store(from.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        let moved = from.entity_id.swap(0, Ordering::Relaxed);
+        to.entity_id.store(moved, Ordering::Relaxed);
+        moved
+    }
+    /// Locks the entities, and in a dense Archetype the entities that will be moved into their slots, for
+    /// [RowRemoval::apply]. Nothing is locked if one of them is borrowed.
+    ///
+    /// This lets the World check that the entities can be removed before it frees their IDs.
+    ///
+    /// # Errors
+    /// [WorldError::IndexOutOfRange] if an index is past the end of the Archetype.
+    /// [WorldError::ComponentBorrowed] if one of the entities is borrowed.
+    pub(crate) fn lock_for_removal(&self, indices: &[u32]) -> Result<RowRemoval<'_>, WorldError> {
+        self.lock_rows(indices, self.0.dense)
+    }
+    /// [Archetype::lock_for_removal] that fills the removed slots with the last entities if `dense` is set,
+    /// instead of following the Archetype.
+    ///
+    /// # Errors
+    /// [WorldError::EntityNotFound] if one of the slots was already removed.
+    fn lock_rows(&self, indices: &[u32], dense: bool) -> Result<RowRemoval<'_>, WorldError> {
+        let free_list = self.0.free_list.lock().unwrap();
+        let len = self.0.entities_len.load(Ordering::Relaxed);
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        if indices.last().is_some_and(|index| *index >= len) {
+            return Err(WorldError::IndexOutOfRange);
+        }
+        // Removed slots have the ID 0, which is only also used by the first Entity
+        let removed = indices.iter().any(|index| {
+            self.0.entity_data[*index as usize]
+                .entity_id
+                .load(Ordering::Relaxed)
+                == 0
+                && free_list.contains(index)
+        });
+        if removed {
+            return Err(WorldError::EntityNotFound);
+        }
+        let moves = if dense {
+            // The live entities past the new length fill the slots below it
+            let end = len - indices.len() as u32;
+            let sources = (end..len).filter(|index| indices.binary_search(index).is_err());
+            let holes = indices.iter().copied().filter(|index| *index < end);
+            sources.zip(holes).collect()
+        } else {
+            Vec::new()
+        };
+        let mut removal = RowRemoval {
+            archetype: self,
+            free_list,
+            dense,
+            len,
+            locked: Vec::with_capacity(indices.len() + moves.len()),
+            indices,
+            moves,
+        };
+        for index in removal
+            .indices
+            .iter()
+            .chain(removal.moves.iter().map(|(from, _)| from))
+        {
+            if !self.try_lock_row(*index) {
+                return Err(WorldError::ComponentBorrowed);
+            }
+            removal.locked.push(*index);
+        }
+        Ok(removal)
     }
 
     /// Returns a Mutable reference to the Component within the Entity.
     ///
+    /// Marks the Component as changed at the current change tick.
+    /// The refs borrow the Archetype, so it can not be resized or dropped while they are alive.
+    ///
     /// # Returns
     /// Ok(Option<MutComponentRef>) if was component is unlocked.
     /// Err(()) if the component is locked.
     #[allow(clippy::result_unit_err)]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
     pub fn get_comp_mut<'comp, T: ComponentLookup<'comp>>(
-        &self,
+        &'comp self,
         entity_index: u32,
     ) -> Result<Option<T::MutResponse>, ()> {
         let inner = &self.0;
+        let location = Location::caller();
 
         if entity_index >= inner.entities_len.load(Ordering::Relaxed) {
             return Ok(None);
@@ -187,20 +512,25 @@ impl Archetype {
         if !data.is_unlocked() {
             return Err(());
         }
+        let borrows = inner.borrows(entity_index as usize);
+        let policy = self.borrow_policy();
         let ptr = data.inner_ptrs.load(Ordering::Relaxed);
+        let tick = inner.change_tick.load(Ordering::Relaxed);
 
         let data = unsafe {
             T::return_mut(|typ| {
                 if let Some((offset, index)) = self.0.component_offsets.get(typ) {
-                    let anti_race_byte = &data.anti_racey_bytes[*index as usize];
-                    let v = anti_race_byte.compare_exchange(
-                        0,
-                        255,
-                        Ordering::Relaxed,
-                        Ordering::Relaxed,
-                    );
-                    if v.is_ok() {
-                        Some((anti_race_byte.clone(), ptr.add(*offset)))
+                    let borrow = &borrows[*index as usize];
+                    let v = borrow::try_write(borrow, policy);
+                    if v && (self.column_blocks(*index, true) || !data.is_unlocked()) {
+                        borrow::release_write(borrow);
+                        None
+                    } else if v {
+                        data.changed_ticks[*index as usize].store(tick, Ordering::Relaxed);
+                        Some((
+                            self.taken(entity_index, *index, true, location),
+                            ptr.add(*offset),
+                        ))
                     } else {
                         None
                     }
@@ -215,15 +545,19 @@ impl Archetype {
 
     /// Returns a reference to the Component within the Entity.
     ///
+    /// The refs borrow the Archetype, so it can not be resized or dropped while they are alive.
+    ///
     /// # Returns
     /// Ok(Option<MutComponentRef>) if was component is unlocked.
     /// Err(()) if the component is locked.
     #[allow(clippy::result_unit_err)]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
     pub fn get_comp<'comp, T: ComponentLookup<'comp>>(
-        &self,
+        &'comp self,
         entity_index: u32,
     ) -> Result<Option<T::RefResponse>, ()> {
         let inner = &self.0;
+        let location = Location::caller();
 
         if entity_index >= inner.entities_len.load(Ordering::Relaxed) {
             return Ok(None);
@@ -232,14 +566,19 @@ impl Archetype {
         if !data.is_unlocked() {
             return Err(());
         }
+        let borrows = inner.borrows(entity_index as usize);
+        let policy = self.borrow_policy();
         let data = unsafe {
             T::return_ref(|typ| {
                 if let Some((offset, index)) = self.0.component_offsets.get(typ) {
-                    let anti_racey_byte = &data.anti_racey_bytes[*index as usize];
-                    let v = anti_racey_byte.fetch_add(1, Ordering::Relaxed);
-                    if v < 254 {
+                    let borrow = &borrows[*index as usize];
+                    let v = borrow::try_read(borrow, policy);
+                    if v && (self.column_blocks(*index, false) || !data.is_unlocked()) {
+                        borrow::release_read(borrow);
+                        None
+                    } else if v {
                         Some((
-                            anti_racey_byte.clone(),
+                            self.taken(entity_index, *index, false, location),
                             data.inner_ptrs.load(Ordering::Relaxed).add(*offset),
                         ))
                     } else {
@@ -253,6 +592,231 @@ impl Archetype {
 
         Ok(data)
     }
+
+    /// Same as [Archetype::get_comp], but the ref holds a clone of the Archetype instead of borrowing it.
+    #[allow(clippy::result_unit_err)]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub fn get_comp_owned<T: Component>(
+        &self,
+        entity_index: u32,
+    ) -> Result<Option<OwnedComponentRef<T>>, ()> {
+        let component = self.get_comp::<T>(entity_index)?;
+        Ok(component.map(|component| unsafe { component.into_owned(self.clone()) }))
+    }
+    /// Same as [Archetype::get_comp_mut], but the ref holds a clone of the Archetype instead of borrowing it.
+    #[allow(clippy::result_unit_err)]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub fn get_comp_mut_owned<T: Component>(
+        &self,
+        entity_index: u32,
+    ) -> Result<Option<OwnedMutComponentRef<T>>, ()> {
+        let component = self.get_comp_mut::<T>(entity_index)?;
+        Ok(component.map(|component| unsafe { component.into_owned(self.clone()) }))
+    }
+
+    /// Takes a shared lock on the Component with the given TypeId.
+    ///
+    /// Same rules as [Archetype::get_comp]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub(crate) fn get_raw(
+        &self,
+        entity_index: u32,
+        typ: &TypeId,
+    ) -> Result<Option<RawComponentRef<'_>>, ()> {
+        let inner = &self.0;
+
+        if entity_index >= inner.entities_len.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let data = &inner.entity_data[entity_index as usize];
+        if !data.is_unlocked() {
+            return Err(());
+        }
+        let Some((offset, index)) = inner.component_offsets.get(typ) else {
+            return Ok(None);
+        };
+        let borrow = &inner.borrows(entity_index as usize)[*index as usize];
+        if !borrow::try_read(borrow, self.borrow_policy()) {
+            return Err(());
+        }
+        if self.column_blocks(*index, false) || !data.is_unlocked() {
+            borrow::release_read(borrow);
+            return Err(());
+        }
+        Ok(Some(RawComponentRef {
+            component: unsafe { data.inner_ptrs.load(Ordering::Relaxed).add(*offset) },
+            ref_count: unsafe { self.taken(entity_index, *index, false, Location::caller()) },
+        }))
+    }
+
+    /// Takes an exclusive lock on the Component with the given TypeId and marks it as changed.
+    ///
+    /// Same rules as [Archetype::get_comp_mut]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub(crate) fn get_raw_mut(
+        &self,
+        entity_index: u32,
+        typ: &TypeId,
+    ) -> Result<Option<RawMutComponentRef<'_>>, ()> {
+        let inner = &self.0;
+
+        if entity_index >= inner.entities_len.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let data = &inner.entity_data[entity_index as usize];
+        if !data.is_unlocked() {
+            return Err(());
+        }
+        let Some((offset, index)) = inner.component_offsets.get(typ) else {
+            return Ok(None);
+        };
+        let borrow = &inner.borrows(entity_index as usize)[*index as usize];
+        if !borrow::try_write(borrow, self.borrow_policy()) {
+            return Err(());
+        }
+        if self.column_blocks(*index, true) || !data.is_unlocked() {
+            borrow::release_write(borrow);
+            return Err(());
+        }
+        data.changed_ticks[*index as usize]
+            .store(inner.change_tick.load(Ordering::Relaxed), Ordering::Relaxed);
+        Ok(Some(RawMutComponentRef {
+            component: unsafe { data.inner_ptrs.load(Ordering::Relaxed).add(*offset) },
+            ref_count: unsafe { self.taken(entity_index, *index, true, Location::caller()) },
+        }))
+    }
+
+    /// Hands a borrow taken with [borrow::try_read] or [borrow::try_write] to a component ref.
+    ///
+    /// # Safety
+    /// The borrow state is freed together with the component data, so `'a` must not outlive it.
+    /// `location` is where the borrow was requested, see [std::panic::Location::caller].
+    #[cfg_attr(not(feature = "debug-borrows"), allow(unused_variables))]
+    unsafe fn taken<'a>(
+        &self,
+        entity_index: u32,
+        component: u32,
+        mutable: bool,
+        location: &'static Location<'static>,
+    ) -> Borrow<'a> {
+        let state = &self.0.borrows(entity_index as usize)[component as usize];
+        Borrow {
+            state: &*(state as *const AtomicU8),
+            #[cfg(feature = "debug-borrows")]
+            record: {
+                let log = &self.0.borrow_log;
+                let id = log.record(
+                    entity_index,
+                    self.0.components[component as usize].name,
+                    mutable,
+                    self.0.change_tick.load(Ordering::Relaxed),
+                    location,
+                );
+                Some((&*(log as *const borrow::BorrowLog), id))
+            },
+        }
+    }
+    /// Locks the component column for the whole Archetype.
+    ///
+    /// Fails if the column is locked in a conflicting way, or if any entity has a conflicting per entity borrow.
+    /// Checking the entities is done once, so the cost is paid per query instead of per entity.
+    pub(crate) fn lock_column(&self, typ: &TypeId, write: bool) -> Option<ColumnGuard<'_>> {
+        let (_, index) = *self.0.component_offsets.get(typ)?;
+        let state = &self.0.column_locks[index as usize];
+        if write {
+            state
+                .compare_exchange(0, COLUMN_WRITE, Ordering::SeqCst, Ordering::Relaxed)
+                .ok()?;
+        } else {
+            state
+                .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |readers| {
+                    (readers < COLUMN_WRITE - 1).then_some(readers + 1)
+                })
+                .ok()?;
+        }
+        let guard = ColumnGuard { state, write };
+        // Per entity borrows take their lock first and then check the column, so one of the two sides sees the other
+        let entities_len = self.0.entities_len.load(Ordering::Relaxed) as usize;
+        for (entity_index, data) in self.0.entity_data[..entities_len].iter().enumerate() {
+            let borrow = self.0.borrows(entity_index)[index as usize].load(Ordering::SeqCst);
+            if !data.is_unlocked()
+                || borrow::is_written(borrow)
+                || (write && !borrow::is_unborrowed(borrow))
+            {
+                return None;
+            }
+        }
+        Some(guard)
+    }
+    /// Sets who gets a component when readers and writers compete for it.
+    pub fn set_borrow_policy(&self, policy: BorrowPolicy) {
+        self.0.borrow_policy.store(policy as u8, Ordering::Relaxed);
+    }
+    pub fn borrow_policy(&self) -> BorrowPolicy {
+        BorrowPolicy::from_u8(self.0.borrow_policy.load(Ordering::Relaxed))
+    }
+    /// True if the column lock conflicts with a per entity borrow.
+    fn column_blocks(&self, index: u32, write: bool) -> bool {
+        let state = self.0.column_locks[index as usize].load(Ordering::SeqCst);
+        if write {
+            state != 0
+        } else {
+            state == COLUMN_WRITE
+        }
+    }
+    pub(crate) fn any_column_locked(&self) -> bool {
+        self.0
+            .column_locks
+            .iter()
+            .any(|state| state.load(Ordering::SeqCst) != 0)
+    }
+
+    /// Returns the change tick the Component was last mutably borrowed or added at.
+    pub fn changed_tick<T: Component>(&self, entity_index: u32) -> Option<u32> {
+        self.changed_tick_raw(entity_index, &TypeId::of::<T>())
+    }
+
+    pub(crate) fn changed_tick_raw(&self, entity_index: u32, typ: &TypeId) -> Option<u32> {
+        let inner = &self.0;
+        if entity_index >= inner.entities_len.load(Ordering::Relaxed) {
+            return None;
+        }
+        let (_, index) = inner.component_offsets.get(typ)?;
+        let data = &inner.entity_data[entity_index as usize];
+        Some(data.changed_ticks[*index as usize].load(Ordering::Relaxed))
+    }
+}
+
+/// The result of [Archetype::shrink]
+#[derive(Debug, Clone, Default)]
+pub struct ShrinkReport {
+    /// The number of bytes released by the shrink.
+    pub bytes_reclaimed: usize,
+    /// The entity ID and new index of every entity that was moved.
+    pub moved: Vec<(u32, u32)>,
+}
+
+/// Allocates the component data for `rows` entities.
+///
+/// Nothing is allocated when the size is zero.
+fn alloc_rows(row_size: usize, rows: usize) -> *mut u8 {
+    if row_size * rows == 0 {
+        return ptr::NonNull::<u64>::dangling().as_ptr().cast();
+    }
+    unsafe { alloc(Layout::from_size_align_unchecked(row_size * rows, 8)) }
+}
+
+/// # Safety
+/// `ptr` must come from [alloc_rows] with the same sizes.
+unsafe fn dealloc_rows(ptr: *mut u8, row_size: usize, rows: usize) {
+    if row_size * rows != 0 {
+        dealloc(ptr, Layout::from_size_align_unchecked(row_size * rows, 8));
+    }
+}
+
+/// Every entity is locked while an Archetype is resized or shrunk, so the new one starts unborrowed.
+fn new_borrow_states(len: usize) -> Box<[AtomicU8]> {
+    (0..len).map(|_| AtomicU8::new(0)).collect()
 }
 
 #[derive(Debug)]
@@ -269,17 +833,30 @@ pub struct ArchetypeInner {
     pub(crate) home_ptr: AtomicPtr<u8>,
 
     pub(crate) free_list: Mutex<Vec<u32>>,
+    /// See [Archetype::is_dense]
+    pub(crate) dense: bool,
+    /// One lock per component. See [Archetype::lock_column]
+    pub(crate) column_locks: Box<[AtomicU32]>,
+    /// The borrow state of every component of every entity. See [ArchetypeInner::borrows]
+    pub(crate) borrow_states: Box<[AtomicU8]>,
+    /// A [BorrowPolicy] as u8
+    pub(crate) borrow_policy: std::sync::atomic::AtomicU8,
+    #[cfg(feature = "debug-borrows")]
+    pub(crate) borrow_log: borrow::BorrowLog,
     pub(crate) max_size: usize,
+    /// The change tick of the World this Archetype belongs to.
+    pub(crate) change_tick: Arc<AtomicU32>,
 }
 
 impl ArchetypeInner {
-    pub(crate) fn new(mut components: Vec<ComponentInfo>, entity_start_size: usize) -> Self {
+    pub(crate) fn new(
+        mut components: Vec<ComponentInfo>,
+        entity_start_size: usize,
+        change_tick: Arc<AtomicU32>,
+    ) -> Self {
         components.sort_unstable_by_key(|c| c.id);
         let total_size = components.iter().map(|c| c.layout.size()).sum::<usize>();
-        let ptr = unsafe {
-            let layout = Layout::from_size_align_unchecked(total_size * entity_start_size, 8);
-            alloc(layout)
-        };
+        let ptr = alloc_rows(total_size, entity_start_size);
         let mut data = Vec::with_capacity(entity_start_size);
         for entity_index in 0..entity_start_size {
             unsafe {
@@ -287,10 +864,7 @@ impl ArchetypeInner {
                     inner_ptrs: AtomicPtr::new(ptr.add(total_size * entity_index)),
                     entity_id: AtomicU32::new(0),
                     locked: AtomicU8::new(0),
-                    anti_racey_bytes: components
-                        .iter()
-                        .map(|_| Arc::new(AtomicU8::new(0)))
-                        .collect(),
+                    changed_ticks: components.iter().map(|_| AtomicU32::new(0)).collect(),
                 });
             }
         }
@@ -301,7 +875,8 @@ impl ArchetypeInner {
             offset += v.layout.size();
             (v.id, (my_offset, index as u32))
         });
-        //
+        let column_locks = components.iter().map(|_| AtomicU32::new(0)).collect();
+        let borrow_states = new_borrow_states(components.len() * entity_start_size);
         Self {
             component_offsets: TypeIdSet::new(map),
             components: components.into_boxed_slice(),
@@ -309,8 +884,95 @@ impl ArchetypeInner {
             entities_len: AtomicU32::new(0),
             home_ptr: AtomicPtr::new(ptr),
             free_list: Mutex::new(Vec::with_capacity(1)),
+            dense: false,
+            column_locks,
+            borrow_states,
+            borrow_policy: Default::default(),
+            #[cfg(feature = "debug-borrows")]
+            borrow_log: Default::default(),
             max_size: entity_start_size,
+            change_tick,
+        }
+    }
+    /// The borrow state of each component of the entity at `index`.
+    ///
+    /// See [borrow] for the meaning of each state.
+    /// Stored in one array for the whole Archetype, so component refs only hold a reference into it.
+    pub(crate) fn borrows(&self, index: usize) -> &[AtomicU8] {
+        let components = self.components.len();
+        &self.borrow_states[index * components..(index + 1) * components]
+    }
+    /// The size of the components of one entity.
+    pub(crate) fn row_size(&self) -> usize {
+        self.components.iter().map(|c| c.layout.size()).sum()
+    }
+    /// The bytes allocated for the component data and the per entity bookkeeping.
+    pub(crate) fn bytes_allocated(&self) -> usize {
+        let components = self.components.len();
+        let per_component = mem::size_of::<AtomicU8>() + mem::size_of::<AtomicU32>();
+        let per_entity =
+            self.row_size() + mem::size_of::<EntityData>() + per_component * components;
+        per_entity * self.max_size
+            + mem::size_of::<u32>() * self.free_list.lock().unwrap().capacity()
+    }
+    /// Moves the live entities of the old archetype into a new one that is exactly large enough for them.
+    ///
+    /// # Returns
+    /// The new archetype and the entity ID and new index of every entity that moved.
+    pub(crate) fn new_compacted(mut old: ArchetypeInner) -> (Self, Vec<(u32, u32)>) {
+        let mut old_entities = mem::take(&mut old.entity_data);
+        let total_size = old.row_size();
+        let entities_len = old.entities_len.load(Ordering::Relaxed) as usize;
+        let free_list = old.free_list.get_mut().unwrap();
+        free_list.sort_unstable();
+        let live = (0..entities_len)
+            .filter(|index| free_list.binary_search(&(*index as u32)).is_err())
+            .collect::<Vec<_>>();
+        // The old archetype must not drop the moved components
+        free_list.clear();
+        old.entities_len.store(0, Ordering::Relaxed);
+
+        let ptr = alloc_rows(total_size, live.len());
+        let mut new_data = Vec::with_capacity(live.len());
+        let mut moved = Vec::new();
+        for (new_index, old_index) in live.iter().enumerate() {
+            let data = &mut old_entities[*old_index];
+            let entity_id = data.entity_id.load(Ordering::Relaxed);
+            if new_index != *old_index {
+                moved.push((entity_id, new_index as u32));
+            }
+            unsafe {
+                let new_pointer = ptr.add(total_size * new_index);
+                ptr::copy_nonoverlapping(
+                    data.inner_ptrs.load(Ordering::Relaxed),
+                    new_pointer,
+                    total_size,
+                );
+                new_data.push(EntityData {
+                    inner_ptrs: AtomicPtr::new(new_pointer),
+                    entity_id: AtomicU32::new(entity_id),
+                    locked: AtomicU8::new(0),
+                    changed_ticks: mem::take(&mut data.changed_ticks),
+                });
+            }
         }
+        let inner = Self {
+            component_offsets: old.component_offsets.clone(),
+            components: old.components.clone(),
+            entity_data: new_data.into_boxed_slice(),
+            entities_len: AtomicU32::new(live.len() as u32),
+            home_ptr: AtomicPtr::new(ptr),
+            free_list: Mutex::new(Vec::new()),
+            dense: old.dense,
+            column_locks: mem::take(&mut old.column_locks),
+            borrow_states: new_borrow_states(old.components.len() * live.len()),
+            borrow_policy: mem::take(&mut old.borrow_policy),
+            #[cfg(feature = "debug-borrows")]
+            borrow_log: Default::default(),
+            max_size: live.len(),
+            change_tick: old.change_tick.clone(),
+        };
+        (inner, moved)
     }
     /// Clones the data from the old archetype into the new one.
     /// Does not deallocate the old one. That will be done by Drop once it goes out of scope
@@ -322,7 +984,7 @@ impl ArchetypeInner {
             .map(|c| c.layout.size())
             .sum::<usize>();
         let new_size = old_entities.len() + size_increase;
-        let ptr = unsafe { alloc(Layout::from_size_align_unchecked(total_size * new_size, 8)) };
+        let ptr = alloc_rows(total_size, new_size);
         let mut new_data = Vec::with_capacity(new_size);
         for (index, data) in old_entities.iter_mut().enumerate() {
             unsafe {
@@ -336,7 +998,7 @@ impl ArchetypeInner {
                     inner_ptrs: AtomicPtr::new(new_pointer),
                     entity_id: mem::take(&mut data.entity_id),
                     locked: AtomicU8::new(0),
-                    anti_racey_bytes: mem::take(&mut data.anti_racey_bytes),
+                    changed_ticks: mem::take(&mut data.changed_ticks),
                 });
             }
         }
@@ -347,11 +1009,7 @@ impl ArchetypeInner {
                     inner_ptrs: AtomicPtr::new(new_pointer),
                     entity_id: AtomicU32::new(0),
                     locked: AtomicU8::new(0),
-                    anti_racey_bytes: old
-                        .components
-                        .iter()
-                        .map(|_| Arc::new(AtomicU8::new(0)))
-                        .collect(),
+                    changed_ticks: old.components.iter().map(|_| AtomicU32::new(0)).collect(),
                 });
             }
         }
@@ -367,7 +1025,14 @@ impl ArchetypeInner {
             entities_len: mem::take(&mut old.entities_len),
             home_ptr: AtomicPtr::new(ptr),
             free_list: mutex,
+            dense: old.dense,
+            column_locks: mem::take(&mut old.column_locks),
+            borrow_states: new_borrow_states(old.components.len() * new_size),
+            borrow_policy: mem::take(&mut old.borrow_policy),
+            #[cfg(feature = "debug-borrows")]
+            borrow_log: Default::default(),
             max_size: new_size,
+            change_tick: old.change_tick.clone(),
         }
     }
 }
@@ -380,11 +1045,17 @@ impl Drop for ArchetypeInner {
             .map(|c| c.layout.size())
             .sum::<usize>();
         let entities_len = self.entities_len.load(Ordering::Relaxed);
+        // Removed entities have already been dropped
+        let free_list = self.free_list.get_mut().unwrap();
+        free_list.sort_unstable();
 
         for (index, data) in self.entity_data.iter_mut().enumerate() {
             if index >= entities_len as usize {
                 break;
             }
+            if free_list.binary_search(&(index as u32)).is_ok() {
+                continue;
+            }
             for (comp, (_ty, (offset, _))) in
                 self.components.iter().zip(self.component_offsets.0.iter())
             {
@@ -395,8 +1066,11 @@ impl Drop for ArchetypeInner {
             }
         }
         unsafe {
-            let layout = Layout::from_size_align_unchecked(total_size * self.max_size, 8);
-            dealloc(self.home_ptr.load(Ordering::Relaxed), layout);
+            dealloc_rows(
+                self.home_ptr.load(Ordering::Relaxed),
+                total_size,
+                self.max_size,
+            );
         }
     }
 }
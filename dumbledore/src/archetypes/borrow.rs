@@ -0,0 +1,318 @@
+//! The reader/writer protocol behind component borrows.
+//!
+//! Every component of every entity has one byte of state:
+//! ```no_lang
+//! 0                      unborrowed
+//! 1..=MAX_READERS        shared borrows
+//! WRITER                 an exclusive borrow
+//! WRITER_WAITING | n     n > 0 shared borrows and a refused writer. See [BorrowPolicy::WriterPreferring]
+//! ```
+//! Failed attempts never change the state, so a refused borrow can not wrap the reader count.
+//!
+//! The model tests run with `RUSTFLAGS="--cfg loom" cargo test -p dumbledore --release --lib loom`
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicU8;
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+/// The state of a component held by a writer.
+pub(crate) const WRITER: u8 = u8::MAX;
+/// Set on top of the reader count by a refused writer.
+pub(crate) const WRITER_WAITING: u8 = 0x80;
+/// The most shared borrows a component can have at once.
+pub(crate) const MAX_READERS: u8 = 0x7E;
+const READERS: u8 = 0x7F;
+
+/// Decides who gets a component when readers and writers compete for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BorrowPolicy {
+    /// Readers get the component as long as no writer holds it. Writers can starve under constant reads.
+    #[default]
+    ReadPreferring,
+    /// A writer that is refused because of readers stops new readers until the existing readers are gone.
+    ///
+    /// The last reader to leave clears the mark, so a writer that gave up does not keep readers out.
+    /// A writer that retries gets in unless another borrow comes first, in which case it waits again.
+    WriterPreferring,
+}
+
+impl BorrowPolicy {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => BorrowPolicy::WriterPreferring,
+            _ => BorrowPolicy::ReadPreferring,
+        }
+    }
+}
+
+/// Takes a shared borrow.
+pub(crate) fn try_read(state: &AtomicU8, policy: BorrowPolicy) -> bool {
+    state
+        .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |state| {
+            let waiting = state & WRITER_WAITING != 0 && policy == BorrowPolicy::WriterPreferring;
+            if state == WRITER || state & READERS >= MAX_READERS || waiting {
+                None
+            } else {
+                Some(state + 1)
+            }
+        })
+        .is_ok()
+}
+
+/// Adds a shared borrow to a component that is already shared, as done by cloning a ref.
+///
+/// # Panics
+/// If the component already has [MAX_READERS] shared borrows.
+pub(crate) fn add_reader(state: &AtomicU8) {
+    state
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+            (state & READERS < MAX_READERS).then_some(state + 1)
+        })
+        .expect("Too many shared borrows of one component");
+}
+
+/// Releases a shared borrow. The last reader also clears [WRITER_WAITING].
+pub(crate) fn release_read(state: &AtomicU8) {
+    let _ = state.fetch_update(Ordering::Release, Ordering::Relaxed, |state| {
+        Some(match state - 1 {
+            WRITER_WAITING => 0,
+            state => state,
+        })
+    });
+}
+
+/// Takes an exclusive borrow. A waiting writer is let in once the readers are gone.
+pub(crate) fn try_write(state: &AtomicU8, policy: BorrowPolicy) -> bool {
+    if state
+        .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |state| {
+            is_unborrowed(state).then_some(WRITER)
+        })
+        .is_ok()
+    {
+        return true;
+    }
+    if policy == BorrowPolicy::WriterPreferring {
+        // Only marked while readers hold it, as the last of them clears the mark.
+        // If a writer holds it or the readers left in the meantime nothing changes
+        let _ = state.fetch_update(Ordering::SeqCst, Ordering::Relaxed, |state| {
+            (state != WRITER && state & READERS != 0).then_some(state | WRITER_WAITING)
+        });
+    }
+    false
+}
+
+/// Releases an exclusive borrow. Also clears [WRITER_WAITING], as the waiting writer got its turn.
+pub(crate) fn release_write(state: &AtomicU8) {
+    state.store(0, Ordering::Release);
+}
+
+/// True if nothing borrows the component. A waiting writer does not count.
+pub(crate) fn is_unborrowed(state: u8) -> bool {
+    state & !WRITER_WAITING == 0
+}
+
+pub(crate) fn is_written(state: u8) -> bool {
+    state == WRITER
+}
+
+/// A borrow of one component that was taken with [try_read] or [try_write]. Held by the component refs.
+///
+/// With the `debug-borrows` feature it also removes its record from the Archetype once released.
+#[derive(Debug)]
+pub struct Borrow<'a> {
+    pub(crate) state: &'a AtomicU8,
+    #[cfg(feature = "debug-borrows")]
+    pub(crate) record: Option<(&'a BorrowLog, u64)>,
+}
+
+impl<'a> Borrow<'a> {
+    pub(crate) fn release_read(&self) {
+        self.forget_record();
+        release_read(self.state);
+    }
+    pub(crate) fn release_write(&self) {
+        self.forget_record();
+        release_write(self.state);
+    }
+    /// Turns an exclusive borrow into a single shared one.
+    pub(crate) fn downgrade(&self) {
+        #[cfg(feature = "debug-borrows")]
+        if let Some((log, id)) = self.record {
+            if let Some(record) = log.records.lock().unwrap().get_mut(&id) {
+                record.mutable = false;
+            }
+        }
+        self.state.store(1, Ordering::Release);
+    }
+    /// Adds a shared borrow. See [add_reader]
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub(crate) fn clone_read(&self) -> Self {
+        add_reader(self.state);
+        #[cfg(feature = "debug-borrows")]
+        let location = std::panic::Location::caller();
+        Borrow {
+            state: self.state,
+            #[cfg(feature = "debug-borrows")]
+            record: self
+                .record
+                .and_then(|(log, id)| Some((log, log.copy(id, location)?))),
+        }
+    }
+    #[cfg(feature = "debug-borrows")]
+    fn forget_record(&self) {
+        if let Some((log, id)) = self.record {
+            log.records.lock().unwrap().remove(&id);
+        }
+    }
+    #[cfg(not(feature = "debug-borrows"))]
+    fn forget_record(&self) {}
+}
+
+/// The borrows of one Archetype that have not been released yet.
+#[cfg(feature = "debug-borrows")]
+#[derive(Debug, Default)]
+pub(crate) struct BorrowLog {
+    next_id: std::sync::atomic::AtomicU64,
+    pub(crate) records: std::sync::Mutex<std::collections::HashMap<u64, BorrowRecord>>,
+}
+
+/// A [crate::diagnostics::ActiveBorrow] without the parts the Archetype does not know about.
+#[cfg(feature = "debug-borrows")]
+#[derive(Debug, Clone)]
+pub(crate) struct BorrowRecord {
+    pub(crate) entity_index: u32,
+    pub(crate) component: &'static str,
+    pub(crate) mutable: bool,
+    pub(crate) tick: u32,
+    pub(crate) location: &'static std::panic::Location<'static>,
+    pub(crate) context: Option<u64>,
+    pub(crate) thread: String,
+    pub(crate) backtrace: std::sync::Arc<std::backtrace::Backtrace>,
+}
+
+#[cfg(feature = "debug-borrows")]
+impl BorrowLog {
+    pub(crate) fn record(
+        &self,
+        entity_index: u32,
+        component: &'static str,
+        mutable: bool,
+        tick: u32,
+        location: &'static std::panic::Location<'static>,
+    ) -> u64 {
+        let record = BorrowRecord {
+            entity_index,
+            component,
+            mutable,
+            tick,
+            location,
+            context: crate::diagnostics::borrow_context(),
+            thread: thread_name(),
+            backtrace: std::sync::Arc::new(std::backtrace::Backtrace::capture()),
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.records.lock().unwrap().insert(id, record);
+        id
+    }
+    /// Records a clone of a shared borrow, with the location, context, thread and backtrace of the clone.
+    fn copy(&self, id: u64, location: &'static std::panic::Location<'static>) -> Option<u64> {
+        let record = self.records.lock().unwrap().get(&id)?.clone();
+        Some(self.record(
+            record.entity_index,
+            record.component,
+            false,
+            record.tick,
+            location,
+        ))
+    }
+}
+
+#[cfg(feature = "debug-borrows")]
+pub(crate) fn thread_name() -> String {
+    let thread = std::thread::current();
+    match thread.name() {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", thread.id()),
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use ::loom::sync::Arc;
+    use ::loom::thread;
+
+    #[test]
+    fn readers_and_writer() {
+        ::loom::model(|| {
+            let state = Arc::new(AtomicU8::new(0));
+            let value = Arc::new(::loom::cell::UnsafeCell::new(0));
+            let threads = (0..2)
+                .map(|_| {
+                    let state = state.clone();
+                    let value = value.clone();
+                    thread::spawn(move || {
+                        if try_read(&state, BorrowPolicy::ReadPreferring) {
+                            value.with(|value| unsafe { *value });
+                            release_read(&state);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            if try_write(&state, BorrowPolicy::ReadPreferring) {
+                value.with_mut(|value| unsafe { *value += 1 });
+                release_write(&state);
+            }
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(state.load(Ordering::SeqCst), 0);
+        });
+    }
+
+    #[test]
+    fn waiting_writer_gets_in() {
+        ::loom::model(|| {
+            let state = Arc::new(AtomicU8::new(0));
+            assert!(try_read(&state, BorrowPolicy::WriterPreferring));
+            let reader = {
+                let state = state.clone();
+                thread::spawn(move || {
+                    if try_read(&state, BorrowPolicy::WriterPreferring) {
+                        release_read(&state);
+                    }
+                })
+            };
+            assert!(!try_write(&state, BorrowPolicy::WriterPreferring));
+            // Readers arriving after the refusal are turned away
+            assert!(!try_read(&state, BorrowPolicy::WriterPreferring));
+            release_read(&state);
+            reader.join().unwrap();
+            assert!(try_write(&state, BorrowPolicy::WriterPreferring));
+            release_write(&state);
+            assert_eq!(state.load(Ordering::SeqCst), 0);
+        });
+    }
+
+    #[test]
+    fn abandoned_writer_lets_readers_in() {
+        ::loom::model(|| {
+            let state = Arc::new(AtomicU8::new(0));
+            assert!(try_read(&state, BorrowPolicy::WriterPreferring));
+            let writer = {
+                let state = state.clone();
+                thread::spawn(move || {
+                    // Refused once and never retried
+                    assert!(!try_write(&state, BorrowPolicy::WriterPreferring));
+                })
+            };
+            writer.join().unwrap();
+            release_read(&state);
+            assert!(try_read(&state, BorrowPolicy::WriterPreferring));
+            release_read(&state);
+            assert_eq!(state.load(Ordering::SeqCst), 0);
+        });
+    }
+}
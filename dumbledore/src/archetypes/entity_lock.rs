@@ -0,0 +1,195 @@
+use crate::archetypes::arche::{Archetype, EntityData};
+use crate::component::{Component, ComponentLookup};
+use std::any::TypeId;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+
+impl Archetype {
+    /// Locks every component of the entity at once. Other borrows, removal and column locks fail until the guard is dropped.
+    ///
+    /// # Returns
+    /// Ok(None) if the index is out of bounds or the entity was removed.
+    /// Err(()) if the entity or one of its components is borrowed, or someone else is locking it.
+    #[allow(clippy::result_unit_err)]
+    pub fn lock_entity(&self, index: u32) -> Result<Option<EntityGuard<'_>>, ()> {
+        if index >= self.0.entities_len.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if !self.try_lock_row(index) {
+            return Err(());
+        }
+        // Removal holds the lock until the slot is on the free list, so this can not change while locked
+        if self.is_removed(index) {
+            self.entity_data(index).mark_unlocked();
+            return Ok(None);
+        }
+        Ok(Some(EntityGuard {
+            archetype: self,
+            index,
+        }))
+    }
+    /// Waits for [Archetype::lock_entity] to succeed.
+    ///
+    /// While waiting, the entity is marked as locking so new borrows fail and the current ones drain.
+    /// There is no wake up when a borrow is released, so the future wakes itself and retries on every poll.
+    ///
+    /// Resolves to None if the index is out of bounds or the entity was removed, before or while waiting, or moved.
+    /// Only the entity ID is compared, so an entity that was removed and replaced by one with a recycled ID is not noticed.
+    /// Check the generation through the World if that matters.
+    pub fn lock_entity_async(&self, index: u32) -> LockEntity<'_> {
+        LockEntity {
+            archetype: self,
+            index,
+            entity_id: None,
+            marked: false,
+        }
+    }
+    /// Locks the entity if nothing borrows it. Used by everything that needs a whole row, such as removal.
+    ///
+    /// Leaves the entity as it was on failure. Release the lock with [EntityData::mark_unlocked]
+    pub(crate) fn try_lock_row(&self, index: u32) -> bool {
+        let data = self.entity_data(index);
+        if !data.mark_locking() {
+            return false;
+        }
+        if !self.finish_locking(index) {
+            data.cancel_locking();
+            return false;
+        }
+        true
+    }
+    /// Takes the lock of an entity the caller marked with [EntityData::mark_locking]. The mark is kept on failure.
+    fn finish_locking(&self, index: u32) -> bool {
+        // Checked after marking the entity, as column locks check the entities after taking the column
+        !self.any_column_locked()
+            && self
+                .entity_data(index)
+                .try_mark_locked(self.0.borrows(index as usize))
+    }
+    /// True if the slot is on the free list. Removed slots keep the ID 0, so only that one has to be looked up.
+    fn is_removed(&self, index: u32) -> bool {
+        self.entity_data(index).entity_id.load(Ordering::Relaxed) == 0
+            && self.0.free_list.lock().unwrap().contains(&index)
+    }
+    fn entity_data(&self, index: u32) -> &EntityData {
+        &self.0.entity_data[index as usize]
+    }
+}
+
+/// Exclusive access to every component of one entity. See [Archetype::lock_entity]
+///
+/// Released on drop.
+pub struct EntityGuard<'a> {
+    archetype: &'a Archetype,
+    index: u32,
+}
+
+impl EntityGuard<'_> {
+    /// The index of the entity in the Archetype.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn entity_id(&self) -> u32 {
+        self.archetype
+            .entity_data(self.index)
+            .entity_id
+            .load(Ordering::Relaxed)
+    }
+    pub fn get<C: Component>(&self) -> Option<&C> {
+        self.ptr(&TypeId::of::<C>(), false)
+            .map(|ptr| unsafe { &*ptr.cast() })
+    }
+    /// Marks the Component as changed at the current change tick.
+    pub fn get_mut<C: Component>(&mut self) -> Option<&mut C> {
+        self.ptr(&TypeId::of::<C>(), true)
+            .map(|ptr| unsafe { &mut *ptr.cast() })
+    }
+    /// Mutable references to several components at once, such as `(&mut Position, &mut Health)` for `view::<(Position, Health)>()`
+    ///
+    /// Marks the Components as changed at the current change tick.
+    ///
+    /// # Returns
+    /// None if a component is not in the Archetype or listed twice.
+    pub fn view<'g, Q: ComponentLookup<'g>>(&'g mut self) -> Option<Q::PlainMut> {
+        let mut types = Q::type_ids();
+        let len = types.len();
+        types.sort_unstable();
+        types.dedup();
+        if types.len() != len {
+            return None;
+        }
+        unsafe { Q::plain_mut(|typ| self.ptr(typ, true)) }
+    }
+    fn ptr(&self, typ: &TypeId, changed: bool) -> Option<*mut u8> {
+        let inner = &self.archetype.0;
+        let (offset, index) = inner.component_offsets.get(typ)?;
+        let data = self.archetype.entity_data(self.index);
+        if changed {
+            data.changed_ticks[*index as usize]
+                .store(inner.change_tick.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        Some(unsafe { data.inner_ptrs.load(Ordering::Relaxed).add(*offset) })
+    }
+}
+
+impl Drop for EntityGuard<'_> {
+    fn drop(&mut self) {
+        self.archetype.entity_data(self.index).mark_unlocked();
+    }
+}
+
+/// The future returned by [Archetype::lock_entity_async]
+pub struct LockEntity<'a> {
+    archetype: &'a Archetype,
+    index: u32,
+    /// The entity at the index on the first poll.
+    entity_id: Option<u32>,
+    /// True if this future marked the entity as locking. Only the one that set the mark clears it.
+    marked: bool,
+}
+
+impl<'a> Future for LockEntity<'a> {
+    type Output = Option<EntityGuard<'a>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let archetype = this.archetype;
+        if this.index >= archetype.0.entities_len.load(Ordering::Relaxed) {
+            return Poll::Ready(None);
+        }
+        let data = archetype.entity_data(this.index);
+        let entity_id = *this
+            .entity_id
+            .get_or_insert_with(|| data.entity_id.load(Ordering::Relaxed));
+        if !this.marked {
+            // Someone else holds the entity or is waiting for it
+            if !data.mark_locking() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            this.marked = true;
+        }
+        if !archetype.finish_locking(this.index) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        this.marked = false;
+        let guard = EntityGuard {
+            archetype,
+            index: this.index,
+        };
+        let removed =
+            data.entity_id.load(Ordering::Relaxed) != entity_id || archetype.is_removed(this.index);
+        Poll::Ready((!removed).then_some(guard))
+    }
+}
+
+impl Drop for LockEntity<'_> {
+    fn drop(&mut self) {
+        if self.marked {
+            self.archetype.entity_data(self.index).cancel_locking();
+        }
+    }
+}
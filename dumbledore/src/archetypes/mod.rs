@@ -1,4 +1,6 @@
 pub mod arche;
+pub mod borrow;
+pub mod entity_lock;
 
 use crate::component::Component;
 use std::alloc::Layout;
@@ -11,20 +13,30 @@ pub struct ComponentInfo {
     pub(crate) layout: Layout,
     pub(crate) id: TypeId,
     pub(crate) drop: unsafe fn(*mut u8),
+    /// From [std::any::type_name]. Only meant for diagnostics.
+    pub(crate) name: &'static str,
 }
 
 impl ComponentInfo {
     pub fn new<T: Component>() -> Self {
         unsafe fn drop_ptr<T>(ptr: *mut u8) {
-            ptr.drop_in_place()
+            ptr.cast::<T>().drop_in_place()
         }
 
         ComponentInfo {
             layout: Layout::new::<T>(),
             id: TypeId::of::<T>(),
             drop: drop_ptr::<T>,
+            name: std::any::type_name::<T>(),
         }
     }
+    /// The type name of the Component. Only meant for diagnostics, the format is not stable.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
 }
 
 impl PartialEq<Self> for ComponentInfo {
@@ -1,9 +1,8 @@
 use crate::archetypes::ComponentInfo;
 use std::any::TypeId;
 
+use crate::archetypes::borrow::Borrow;
 use crate::component_ref::{ComponentRef, MutComponentRef};
-use std::sync::atomic::AtomicU8;
-use std::sync::Arc;
 
 pub trait Component: Send + Sync + 'static {
     fn component_info() -> ComponentInfo
@@ -18,6 +17,10 @@ pub trait Component: Send + Sync + 'static {
 
 /// A Trait that can be converted into a Archetype.
 pub trait Bundle {
+    /// Hands each component to `f` as a pointer to its data along with its [ComponentInfo].
+    ///
+    /// # Safety
+    /// `f` takes ownership of the component data. The implementation must not drop the components after passing them.
     unsafe fn put_self(self, f: impl FnMut(*mut u8, ComponentInfo))
     where
         Self: Sized;
@@ -35,32 +38,58 @@ pub trait Bundle {
 pub trait ComponentLookup<'comp> {
     type MutResponse;
     type RefResponse;
+    /// Plain mutable references, handed out while the whole column is locked.
+    type PlainMut;
+    /// Plain shared references, handed out while the whole column is locked.
+    type PlainRef;
+    /// The TypeIds of the components being looked up.
+    fn type_ids() -> Vec<TypeId>
+    where
+        Self: Sized;
     #[allow(clippy::missing_safety_doc)]
     unsafe fn return_ref<GE>(get_entity: GE) -> Option<Self::RefResponse>
     where
         Self: Sized,
-        GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)>;
+        GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)>;
     #[allow(clippy::missing_safety_doc)]
     unsafe fn return_mut<GE>(get_entity: GE) -> Option<Self::MutResponse>
     where
         Self: Sized,
-        GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)>;
+        GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)>;
+    /// # Safety
+    /// The pointers returned by `get_ptr` must be valid for `'comp` and not aliased by anything else.
+    unsafe fn plain_mut<GP>(get_ptr: GP) -> Option<Self::PlainMut>
+    where
+        Self: Sized,
+        GP: Fn(&TypeId) -> Option<*mut u8>;
+    /// # Safety
+    /// The pointers returned by `get_ptr` must be valid for `'comp` and not mutably aliased.
+    unsafe fn plain_ref<GP>(get_ptr: GP) -> Option<Self::PlainRef>
+    where
+        Self: Sized,
+        GP: Fn(&TypeId) -> Option<*mut u8>;
 }
 
 impl<'comp, C: Component> ComponentLookup<'comp> for C {
     type MutResponse = MutComponentRef<'comp, C>;
     type RefResponse = ComponentRef<'comp, C>;
+    type PlainMut = &'comp mut C;
+    type PlainRef = &'comp C;
+
+    fn type_ids() -> Vec<TypeId> {
+        vec![TypeId::of::<C>()]
+    }
 
     unsafe fn return_ref<GE>(get_entity: GE) -> Option<Self::RefResponse>
     where
         Self: Sized,
-        GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)>,
+        GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)>,
     {
-        if let Some((arc, ptr)) = get_entity(&TypeId::of::<C>()) {
+        if let Some((borrow, ptr)) = get_entity(&TypeId::of::<C>()) {
             let x = &*ptr.cast();
             Some(ComponentRef {
                 component: x,
-                ref_count: arc,
+                ref_count: borrow,
             })
         } else {
             None
@@ -70,18 +99,34 @@ impl<'comp, C: Component> ComponentLookup<'comp> for C {
     unsafe fn return_mut<GE>(get_entity: GE) -> Option<Self::MutResponse>
     where
         Self: Sized,
-        GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)>,
+        GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)>,
     {
-        if let Some((arc, ptr)) = get_entity(&TypeId::of::<C>()) {
+        if let Some((borrow, ptr)) = get_entity(&TypeId::of::<C>()) {
             let x = &mut *ptr.cast();
             Some(MutComponentRef {
                 component: x,
-                ref_count: arc,
+                ref_count: borrow,
             })
         } else {
             None
         }
     }
+
+    unsafe fn plain_mut<GP>(get_ptr: GP) -> Option<Self::PlainMut>
+    where
+        Self: Sized,
+        GP: Fn(&TypeId) -> Option<*mut u8>,
+    {
+        get_ptr(&TypeId::of::<C>()).map(|ptr| &mut *ptr.cast())
+    }
+
+    unsafe fn plain_ref<GP>(get_ptr: GP) -> Option<Self::PlainRef>
+    where
+        Self: Sized,
+        GP: Fn(&TypeId) -> Option<*mut u8>,
+    {
+        get_ptr(&TypeId::of::<C>()).map(|ptr| &*ptr.cast())
+    }
 }
 
 ///
@@ -96,30 +141,34 @@ impl<'comp, C: Component> ComponentLookup<'comp> for C {
 ///     type MutResponse = (MutComponentRef<'comp, C>,MutComponentRef<'comp, D>);
 ///     type RefResponse = (ComponentRef<'comp, C>,ComponentRef<'comp, D>);
 ///
-///     unsafe fn return_ref<GE>(get_entity: GE) -> Option<Self::RefResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)> {
-///         let (arc_one, data_one) =get_entity(&TypeId::of::<C>())?;
+///     fn type_ids() -> Vec<TypeId> {
+///         vec![TypeId::of::<C>(), TypeId::of::<D>()]
+///     }
+///
+///     unsafe fn return_ref<GE>(get_entity: GE) -> Option<Self::RefResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)> {
+///         let (borrow_one, data_one) =get_entity(&TypeId::of::<C>())?;
 ///         let c = ComponentRef {
 ///             component: &*data_one.cast(),
-///             ref_count: arc_one,
+///             ref_count: borrow_one,
 ///         };
-///         let (arc_two, data_two) = get_entity(&TypeId::of::<D>())?;
+///         let (borrow_two, data_two) = get_entity(&TypeId::of::<D>())?;
 ///         let d = ComponentRef {
 ///             component: &*data_two.cast(),
-///             ref_count: arc_two,
+///             ref_count: borrow_two,
 ///         };
 ///         Some((c,d))
 ///     }
 ///
-///     unsafe fn return_mut<GE>(get_entity: GE) -> Option<Self::MutResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)>{
-///         let (arc_one, data_one) = get_entity(&TypeId::of::<C>())?;
+///     unsafe fn return_mut<GE>(get_entity: GE) -> Option<Self::MutResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)>{
+///         let (borrow_one, data_one) = get_entity(&TypeId::of::<C>())?;
 ///        let c = MutComponentRef {
 ///             component: &mut *data_one.cast(),
-///             ref_count: arc_one,
+///             ref_count: borrow_one,
 ///         };
-///         let (arc_two, data_two) = get_entity(&TypeId::of::<D>())?;
+///         let (borrow_two, data_two) = get_entity(&TypeId::of::<D>())?;
 ///         let d =MutComponentRef{
 ///             component: &mut *data_two.cast(),
-///             ref_count: arc_two,
+///             ref_count: borrow_two,
 ///         };
 ///         Some((c,d))
 ///     }
@@ -131,26 +180,37 @@ macro_rules! define_lookup {
         impl<'comp, $($name: Component),*> ComponentLookup<'comp> for ($($name,)*){
             type MutResponse = ($(MutComponentRef<'comp, $name>,)*);
             type RefResponse = ($(ComponentRef<'comp, $name>,)*);
-            unsafe fn return_ref<GE>(get_entity: GE) -> Option<Self::RefResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)> {
+            type PlainMut = ($(&'comp mut $name,)*);
+            type PlainRef = ($(&'comp $name,)*);
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$name>()),*]
+            }
+            unsafe fn return_ref<GE>(get_entity: GE) -> Option<Self::RefResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)> {
                 $(
-                    let (arc, data) = get_entity(&TypeId::of::<$name>())?;
+                    let (borrow, data) = get_entity(&TypeId::of::<$name>())?;
                     let $name = ComponentRef {
                         component: &*data.cast(),
-                        ref_count: arc,
+                        ref_count: borrow,
                     };
                 )*
                 Some(($($name,)*))
             }
-            unsafe fn return_mut<GE>(get_entity: GE) -> Option<Self::MutResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Arc<AtomicU8>, *mut u8)>    {
+            unsafe fn return_mut<GE>(get_entity: GE) -> Option<Self::MutResponse> where Self: Sized, GE: Fn(&TypeId) -> Option<(Borrow<'comp>, *mut u8)>    {
                 $(
-                    let (arc, data) = get_entity(&TypeId::of::<$name>())?;
+                    let (borrow, data) = get_entity(&TypeId::of::<$name>())?;
                     let $name = MutComponentRef {
                         component: &mut *data.cast(),
-                        ref_count: arc,
+                        ref_count: borrow,
                     };
                 )*
                 Some(($($name,)*))
             }
+            unsafe fn plain_mut<GP>(get_ptr: GP) -> Option<Self::PlainMut> where Self: Sized, GP: Fn(&TypeId) -> Option<*mut u8> {
+                Some(($(&mut *get_ptr(&TypeId::of::<$name>())?.cast::<$name>(),)*))
+            }
+            unsafe fn plain_ref<GP>(get_ptr: GP) -> Option<Self::PlainRef> where Self: Sized, GP: Fn(&TypeId) -> Option<*mut u8> {
+                Some(($(&*get_ptr(&TypeId::of::<$name>())?.cast::<$name>(),)*))
+            }
         }
     }
 }
@@ -1,88 +1,317 @@
 use std::fmt::{Debug, Display, Formatter};
+use std::mem::{self, ManuallyDrop};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
 
+use crate::archetypes::arche::Archetype;
+use crate::archetypes::borrow::Borrow;
 use crate::component::Component;
-use std::sync::atomic::AtomicU8;
-use std::sync::Arc;
 
 /// A Reference to a Component.
 ///
 /// Drops the Ref Count down when the Component is dropped.
-pub struct ComponentRef<'comp, T: Component> {
+pub struct ComponentRef<'comp, T: ?Sized> {
     pub(crate) component: &'comp T,
-    pub(crate) ref_count: Arc<AtomicU8>,
+    pub(crate) ref_count: Borrow<'comp>,
 }
 
-impl<T: Component> Drop for ComponentRef<'_, T> {
+impl<T: ?Sized> Drop for ComponentRef<'_, T> {
     fn drop(&mut self) {
-        self.ref_count
-            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+        self.ref_count.release_read();
     }
 }
 
-impl<T: Component> AsRef<T> for ComponentRef<'_, T> {
+impl<T: ?Sized> AsRef<T> for ComponentRef<'_, T> {
     fn as_ref(&self) -> &T {
         self.component
     }
 }
 
-impl<T: Component + PartialEq> PartialEq for ComponentRef<'_, T> {
+impl<T: ?Sized + PartialEq> PartialEq for ComponentRef<'_, T> {
     fn eq(&self, other: &Self) -> bool {
         self.component == other.component
     }
 }
 
-impl<T: Component + Debug> Debug for ComponentRef<'_, T> {
+impl<T: ?Sized + Debug> Debug for ComponentRef<'_, T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.component.fmt(f)
     }
 }
 
-impl<T: Component + Display> Display for ComponentRef<'_, T> {
+impl<T: ?Sized + Display> Display for ComponentRef<'_, T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.component.fmt(f)
     }
 }
 
-impl<T: Component> Clone for ComponentRef<'_, T> {
+impl<T: ?Sized> Clone for ComponentRef<'_, T> {
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
     fn clone(&self) -> Self {
-        self.ref_count
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         Self {
             component: self.component,
-            ref_count: self.ref_count.clone(),
+            ref_count: self.ref_count.clone_read(),
         }
     }
 }
 
+/// A shared lock on a Component whose type is not known.
+///
+/// Drops the Ref Count down when dropped.
+pub(crate) struct RawComponentRef<'comp> {
+    pub(crate) component: *mut u8,
+    pub(crate) ref_count: Borrow<'comp>,
+}
+
+impl Drop for RawComponentRef<'_> {
+    fn drop(&mut self) {
+        self.ref_count.release_read();
+    }
+}
+
+/// An exclusive lock on a Component whose type is not known.
+///
+/// Releases the lock when dropped.
+pub(crate) struct RawMutComponentRef<'comp> {
+    pub(crate) component: *mut u8,
+    pub(crate) ref_count: Borrow<'comp>,
+}
+
+impl Drop for RawMutComponentRef<'_> {
+    fn drop(&mut self) {
+        self.ref_count.release_write();
+    }
+}
+
 /// A Reference to a Component.
 ///
 /// Drops the Ref Count down when the Component is dropped.
-pub struct MutComponentRef<'comp, T: Component> {
+pub struct MutComponentRef<'comp, T: ?Sized> {
     pub(crate) component: &'comp mut T,
-    pub(crate) ref_count: Arc<AtomicU8>,
+    pub(crate) ref_count: Borrow<'comp>,
 }
 
-impl<T: Component + Debug> Debug for MutComponentRef<'_, T> {
+impl<T: ?Sized + Debug> Debug for MutComponentRef<'_, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.component.fmt(f)
     }
 }
 
-impl<T: Component> Drop for MutComponentRef<'_, T> {
+impl<T: ?Sized> Drop for MutComponentRef<'_, T> {
     fn drop(&mut self) {
-        self.ref_count
-            .fetch_sub(255, std::sync::atomic::Ordering::Relaxed);
+        self.ref_count.release_write();
     }
 }
 
-impl<T: Component> AsRef<T> for MutComponentRef<'_, T> {
+impl<T: ?Sized> AsRef<T> for MutComponentRef<'_, T> {
     fn as_ref(&self) -> &T {
         self.component
     }
 }
 
-impl<T: Component> AsMut<T> for MutComponentRef<'_, T> {
+impl<T: ?Sized> AsMut<T> for MutComponentRef<'_, T> {
     fn as_mut(&mut self) -> &mut T {
         self.component
     }
 }
+
+impl<T: ?Sized> Deref for ComponentRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.component
+    }
+}
+
+impl<T: ?Sized> Deref for MutComponentRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.component
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutComponentRef<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.component
+    }
+}
+
+impl<'comp, T: ?Sized> ComponentRef<'comp, T> {
+    /// Narrows the ref to a part of the Component, such as one of its fields. The Component stays locked.
+    ///
+    /// An associated function like [std::cell::Ref::map], so it does not shadow methods of the Component.
+    pub fn map<U: ?Sized>(this: Self, f: impl FnOnce(&T) -> &U) -> ComponentRef<'comp, U> {
+        // Called before taking the borrow out, so it is still released if `f` panics
+        let component = f(this.component);
+        let this = ManuallyDrop::new(this);
+        ComponentRef {
+            component,
+            // The borrow moves to the new ref, which releases it
+            ref_count: unsafe { ptr::read(&this.ref_count) },
+        }
+    }
+}
+
+impl<'comp, T: ?Sized> MutComponentRef<'comp, T> {
+    /// Narrows the ref to a part of the Component, such as one of its fields. The Component stays locked.
+    ///
+    /// An associated function like [std::cell::RefMut::map], so it does not shadow methods of the Component.
+    pub fn map<U: ?Sized>(
+        this: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MutComponentRef<'comp, U> {
+        // Called before taking the borrow out, so it is still released if `f` panics
+        let component = f(unsafe { ptr::read(&this.component) });
+        let this = ManuallyDrop::new(this);
+        // The borrow moves to the new ref, which releases it
+        MutComponentRef {
+            component,
+            ref_count: unsafe { ptr::read(&this.ref_count) },
+        }
+    }
+    /// Turns the exclusive lock into a shared one without releasing it in between.
+    pub fn downgrade(this: Self) -> ComponentRef<'comp, T> {
+        let this = ManuallyDrop::new(this);
+        let (component, ref_count) =
+            unsafe { (ptr::read(&this.component), ptr::read(&this.ref_count)) };
+        ref_count.downgrade();
+        ComponentRef {
+            component,
+            ref_count,
+        }
+    }
+}
+
+impl<'comp, T: Component> ComponentRef<'comp, T> {
+    /// Keeps the borrow alive with a clone of the Archetype instead of a lifetime.
+    ///
+    /// # Safety
+    /// The ref must have been taken from `archetype`.
+    pub(crate) unsafe fn into_owned(self, archetype: Archetype) -> OwnedComponentRef<T> {
+        let this = ManuallyDrop::new(self);
+        OwnedComponentRef {
+            component: this.component,
+            ref_count: mem::transmute::<Borrow<'comp>, Borrow<'static>>(ptr::read(&this.ref_count)),
+            _archetype: archetype,
+        }
+    }
+}
+
+impl<'comp, T: Component> MutComponentRef<'comp, T> {
+    /// Keeps the borrow alive with a clone of the Archetype instead of a lifetime.
+    ///
+    /// # Safety
+    /// The ref must have been taken from `archetype`.
+    pub(crate) unsafe fn into_owned(self, archetype: Archetype) -> OwnedMutComponentRef<T> {
+        let mut this = ManuallyDrop::new(self);
+        OwnedMutComponentRef {
+            component: &mut *this.component,
+            ref_count: mem::transmute::<Borrow<'comp>, Borrow<'static>>(ptr::read(&this.ref_count)),
+            _archetype: archetype,
+        }
+    }
+}
+
+/// A shared Reference to a Component that keeps its Archetype alive, so it can be held across `.await` points.
+///
+/// Taken with [Archetype::get_comp_owned]. The Archetype can not be resized while it is held.
+pub struct OwnedComponentRef<T: Component> {
+    component: *const T,
+    ref_count: Borrow<'static>,
+    /// Owns the component data and the borrow state. Dropped after the borrow is released.
+    _archetype: Archetype,
+}
+
+// The Component is only reachable through the borrow, which follows the same rules as ComponentRef
+unsafe impl<T: Component> Send for OwnedComponentRef<T> {}
+unsafe impl<T: Component> Sync for OwnedComponentRef<T> {}
+
+impl<T: Component> Drop for OwnedComponentRef<T> {
+    fn drop(&mut self) {
+        self.ref_count.release_read();
+    }
+}
+
+impl<T: Component> AsRef<T> for OwnedComponentRef<T> {
+    fn as_ref(&self) -> &T {
+        unsafe { &*self.component }
+    }
+}
+
+impl<T: Component> Deref for OwnedComponentRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.as_ref()
+    }
+}
+
+impl<T: Component + Debug> Debug for OwnedComponentRef<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
+
+impl<T: Component> Clone for OwnedComponentRef<T> {
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    fn clone(&self) -> Self {
+        Self {
+            component: self.component,
+            ref_count: self.ref_count.clone_read(),
+            _archetype: self._archetype.clone(),
+        }
+    }
+}
+
+/// A mutable Reference to a Component that keeps its Archetype alive, so it can be held across `.await` points.
+///
+/// Taken with [Archetype::get_comp_mut_owned]. The Archetype can not be resized while it is held.
+pub struct OwnedMutComponentRef<T: Component> {
+    component: *mut T,
+    ref_count: Borrow<'static>,
+    /// Owns the component data and the borrow state. Dropped after the borrow is released.
+    _archetype: Archetype,
+}
+
+// The Component is only reachable through the borrow, which follows the same rules as MutComponentRef
+unsafe impl<T: Component> Send for OwnedMutComponentRef<T> {}
+unsafe impl<T: Component> Sync for OwnedMutComponentRef<T> {}
+
+impl<T: Component> Drop for OwnedMutComponentRef<T> {
+    fn drop(&mut self) {
+        self.ref_count.release_write();
+    }
+}
+
+impl<T: Component> AsRef<T> for OwnedMutComponentRef<T> {
+    fn as_ref(&self) -> &T {
+        unsafe { &*self.component }
+    }
+}
+
+impl<T: Component> AsMut<T> for OwnedMutComponentRef<T> {
+    fn as_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.component }
+    }
+}
+
+impl<T: Component> Deref for OwnedMutComponentRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.as_ref()
+    }
+}
+
+impl<T: Component> DerefMut for OwnedMutComponentRef<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.as_mut()
+    }
+}
+
+impl<T: Component + Debug> Debug for OwnedMutComponentRef<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
@@ -0,0 +1,91 @@
+//! Finding borrows that are held for too long, enabled by the `debug-borrows` feature.
+//!
+//! A leaked [crate::component_ref::MutComponentRef] locks its component forever, which makes
+//! [crate::archetypes::arche::Archetype::resize] fail. [World::active_borrows] lists who is holding what.
+use crate::world::World;
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::panic::Location;
+use std::sync::Arc;
+
+thread_local! {
+    static BORROW_CONTEXT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// A borrow that has not been released yet. Returned by [World::active_borrows]
+#[derive(Debug, Clone)]
+pub struct ActiveBorrow {
+    pub archetype: u32,
+    pub entity_index: u32,
+    pub entity_id: u32,
+    /// See [crate::archetypes::ComponentInfo::name]
+    pub component: &'static str,
+    pub mutable: bool,
+    /// The change tick the borrow was taken at.
+    pub tick: u32,
+    /// The code that asked for the borrow, such as the call to [crate::archetypes::arche::Archetype::get_comp].
+    pub location: &'static Location<'static>,
+    /// The id given to [with_borrow_context] by the code that took the borrow.
+    pub context: Option<u64>,
+    /// The name or id of the thread that took the borrow.
+    pub thread: String,
+    /// Only captured if `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set. See [Backtrace::capture]
+    pub backtrace: Arc<Backtrace>,
+}
+
+/// Tags every borrow taken on this thread while `f` runs with `context`, such as the id of a task or a request.
+///
+/// Async tasks move between threads, so wrap each poll of the task instead of the whole task.
+/// The previous context is restored afterwards, even if `f` panics.
+pub fn with_borrow_context<R>(context: u64, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<u64>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            BORROW_CONTEXT.with(|current| current.set(self.0));
+        }
+    }
+    let _restore = Restore(BORROW_CONTEXT.with(|current| current.replace(Some(context))));
+    f()
+}
+
+/// The context set by [with_borrow_context] on this thread.
+pub fn borrow_context() -> Option<u64> {
+    BORROW_CONTEXT.with(|current| current.get())
+}
+
+impl World {
+    /// Every component borrow that has not been released, oldest first.
+    pub fn active_borrows(&self) -> Vec<ActiveBorrow> {
+        let mut borrows = Vec::new();
+        for (id, archetype) in self.archetypes.iter() {
+            let records = archetype.0.borrow_log.records.lock().unwrap();
+            borrows.extend(records.values().map(|record| {
+                ActiveBorrow {
+                    archetype: *id,
+                    entity_index: record.entity_index,
+                    entity_id: archetype.0.entity_data[record.entity_index as usize]
+                        .entity_id
+                        .load(std::sync::atomic::Ordering::Relaxed),
+                    component: record.component,
+                    mutable: record.mutable,
+                    tick: record.tick,
+                    location: record.location,
+                    context: record.context,
+                    thread: record.thread.clone(),
+                    backtrace: record.backtrace.clone(),
+                }
+            }));
+        }
+        borrows.sort_by_key(|borrow| borrow.tick);
+        borrows
+    }
+    /// The borrows held for more than `ticks` change ticks, oldest first.
+    ///
+    /// Meant to be checked after [World::increment_change_tick], to report or log leaked borrows.
+    pub fn long_borrows(&self, ticks: u32) -> Vec<ActiveBorrow> {
+        let now = self.change_tick();
+        let mut borrows = self.active_borrows();
+        borrows.retain(|borrow| now.wrapping_sub(borrow.tick) > ticks);
+        borrows
+    }
+}
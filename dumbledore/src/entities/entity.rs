@@ -1,6 +1,7 @@
 use std::num::NonZeroU32;
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entity {
     pub(crate) generation: NonZeroU32,
     pub id: u32,
@@ -25,7 +26,8 @@ impl Into<u32> for Entity {
 pub struct EntityMeta {
     pub(crate) generation: NonZeroU32,
     pub in_use: bool,
-    pub location: EntityLocation,
+    /// None if the Entity has no components.
+    pub location: Option<EntityLocation>,
 }
 
 impl Default for EntityMeta {
@@ -33,12 +35,16 @@ impl Default for EntityMeta {
         EntityMeta {
             generation: NonZeroU32::new(1).unwrap(),
             in_use: false,
-            location: EntityLocation::default(),
+            location: None,
         }
     }
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+/// Where the components of an Entity are stored.
+///
+/// Entities without components do not have a location.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EntityLocation {
     // Archetype ID - Will rarely change.
     pub archetype: u32,
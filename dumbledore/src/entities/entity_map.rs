@@ -0,0 +1,44 @@
+use crate::entities::entity::Entity;
+use std::collections::HashMap;
+
+/// Maps Entities from one World to the Entities they became in another.
+///
+/// Produced when loading a snapshot or mirroring entities, so references stored inside components can be rewritten.
+#[derive(Debug, Clone, Default)]
+pub struct EntityMap(pub(crate) HashMap<Entity, Entity>);
+
+impl EntityMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, from: Entity, to: Entity) -> Option<Entity> {
+        self.0.insert(from, to)
+    }
+    pub fn remove(&mut self, from: &Entity) -> Option<Entity> {
+        self.0.remove(from)
+    }
+    /// Returns the Entity `from` was mapped to.
+    pub fn get(&self, from: &Entity) -> Option<&Entity> {
+        self.0.get(from)
+    }
+    /// Returns the Entity `from` was mapped to or `from` itself if it is not in the map.
+    pub fn map(&self, from: &Entity) -> Entity {
+        self.0.get(from).cloned().unwrap_or_else(|| from.clone())
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&Entity, &Entity)> {
+        self.0.iter()
+    }
+}
+
+/// Implemented by Components that store Entities.
+///
+/// Called after the Component has been loaded into a different World so the stored Entities point at the right place.
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &EntityMap);
+}
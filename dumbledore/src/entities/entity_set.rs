@@ -1,128 +1,404 @@
 use crate::entities::entity::{Entity, EntityLocation, EntityMeta};
-use std::collections::VecDeque;
-use std::mem;
 use std::num::NonZeroU32;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::sync::{Arc, Mutex};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Marks the slot as in use in [EntitySlot::state]
+const IN_USE: u32 = 1 << 31;
+const GENERATION_MASK: u32 = !IN_USE;
+/// The end of the free list
+const NONE: u32 = u32::MAX;
+/// The location of an Entity that is not in an Archetype.
+const NO_LOCATION: u64 = u64::MAX;
 
 #[derive(Debug, Clone)]
 pub struct EntitySet(pub Arc<EntitySetInner>);
 
+/// The state of one Entity ID.
+///
+/// Every field is an atomic so the slot can be read and updated without a lock.
+#[derive(Debug)]
+pub(crate) struct EntitySlot {
+    /// The generation in the low 31 bits. The high bit is set while the ID is in use.
+    pub(crate) state: AtomicU32,
+    /// The archetype in the high 32 bits and the index in the low 32 bits.
+    pub(crate) location: AtomicU64,
+    /// The next slot in the free list.
+    pub(crate) next_free: AtomicU32,
+}
+
+impl Default for EntitySlot {
+    fn default() -> Self {
+        Self::from_meta(&EntityMeta::default())
+    }
+}
+
+impl EntitySlot {
+    pub(crate) fn from_meta(meta: &EntityMeta) -> Self {
+        let slot = EntitySlot {
+            state: AtomicU32::new(0),
+            location: AtomicU64::new(NO_LOCATION),
+            next_free: AtomicU32::new(NONE),
+        };
+        slot.store_meta(meta);
+        slot
+    }
+    pub(crate) fn store_meta(&self, meta: &EntityMeta) {
+        let in_use = if meta.in_use { IN_USE } else { 0 };
+        self.state.store(
+            (meta.generation.get() & GENERATION_MASK) | in_use,
+            Ordering::Release,
+        );
+        self.location
+            .store(pack_option(meta.location.as_ref()), Ordering::Release);
+    }
+    pub(crate) fn meta(&self) -> EntityMeta {
+        let state = self.state.load(Ordering::Acquire);
+        EntityMeta {
+            generation: generation_of(state),
+            in_use: state & IN_USE != 0,
+            location: unpack_option(self.location.load(Ordering::Acquire)),
+        }
+    }
+}
+
+fn generation_of(state: u32) -> NonZeroU32 {
+    NonZeroU32::new(state & GENERATION_MASK).unwrap_or(NonZeroU32::MIN)
+}
+
+fn pack_location(location: &EntityLocation) -> u64 {
+    ((location.archetype as u64) << 32) | location.index as u64
+}
+
+fn pack_option(location: Option<&EntityLocation>) -> u64 {
+    location.map_or(NO_LOCATION, pack_location)
+}
+
+fn unpack_option(packed: u64) -> Option<EntityLocation> {
+    (packed != NO_LOCATION).then(|| unpack_location(packed))
+}
+
+fn unpack_location(packed: u64) -> EntityLocation {
+    EntityLocation {
+        archetype: (packed >> 32) as u32,
+        index: packed as u32,
+    }
+}
+
+/// The most segments an EntitySet can grow to.
+const MAX_SEGMENTS: usize = 32;
+
+/// The Entity slots, stored in segments that never move once allocated.
+///
+/// Segment `k` holds `base << k` slots, starting at ID `base * (2^k - 1)`. Growing only publishes new segments,
+/// so every clone of the EntitySet keeps seeing the same slots.
 #[derive(Debug)]
 pub struct EntitySetInner {
-    // Active Entities
-    pub(crate) entities: Box<[Mutex<EntityMeta>]>,
+    base: u32,
+    segments: [AtomicPtr<EntitySlot>; MAX_SEGMENTS],
+    /// The number of slots in the published segments.
+    pub(crate) capacity: AtomicU32,
     // The next available entity ID.
     pub(crate) length: AtomicU32,
-    // Entities before the length that are free
-    pub(crate) free_list: Mutex<VecDeque<usize>>,
-
-    pub(crate) locked: AtomicBool,
+    /// The head of the free list of entities before the length.
+    ///
+    /// The low 32 bits are the slot and the high 32 bits a counter bumped on every update, so a stale head never matches.
+    pub(crate) free_head: AtomicU64,
 }
 
 impl EntitySetInner {
     pub fn new(capacity: u32) -> Self {
-        let mut entities = Vec::with_capacity(capacity as usize);
-        for _ in 0..capacity {
-            entities.push(Mutex::new(EntityMeta::default()));
-        }
-        Self {
-            entities: entities.into_boxed_slice(),
+        let inner = Self {
+            base: capacity.max(1),
+            segments: std::array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+            capacity: AtomicU32::new(0),
             length: AtomicU32::new(0),
-            free_list: Mutex::new(VecDeque::new()),
-            locked: Default::default(),
+            free_head: AtomicU64::new(NONE as u64),
+        };
+        if capacity > 0 {
+            inner.grow(capacity);
         }
+        inner
+    }
+
+    /// The first ID in the segment and the number of slots in it.
+    fn segment_range(&self, segment: usize) -> (u64, u64) {
+        let base = self.base as u64;
+        (base * ((1u64 << segment) - 1), base << segment)
     }
 
-    pub fn reallocate(&self, increase: u32) -> Self {
-        let new_capacity = self.entities.len() as u32 + increase;
-        let mut new_entities = Vec::with_capacity(new_capacity as usize);
-        for i in self.entities.iter() {
-            new_entities.push(Mutex::new(i.lock().unwrap().clone()));
+    /// Publishes segments until at least `additional` more slots are available.
+    ///
+    /// Can be called while other threads use the EntitySet.
+    ///
+    /// # Returns
+    /// False if the EntitySet can not grow that large.
+    pub fn grow(&self, additional: u32) -> bool {
+        self.grow_to(self.capacity.load(Ordering::Acquire) as u64 + additional as u64)
+    }
+    /// Publishes segments until the capacity is at least `target`.
+    pub(crate) fn grow_to(&self, target: u64) -> bool {
+        for segment in 0..MAX_SEGMENTS {
+            let (start, len) = self.segment_range(segment);
+            if start >= target {
+                return true;
+            }
+            if start + len > u32::MAX as u64 {
+                return false;
+            }
+            if self.segments[segment].load(Ordering::Acquire).is_null() {
+                let slots = (0..len)
+                    .map(|_| EntitySlot::default())
+                    .collect::<Box<[_]>>();
+                let slots = Box::into_raw(slots) as *mut EntitySlot;
+                if self.segments[segment]
+                    .compare_exchange(ptr::null_mut(), slots, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    // Another thread published this segment first
+                    unsafe {
+                        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                            slots,
+                            len as usize,
+                        )));
+                    }
+                }
+            }
+            self.capacity
+                .fetch_max((start + len) as u32, Ordering::AcqRel);
         }
-        for _ in self.entities.len()..new_capacity as usize {
-            new_entities.push(Mutex::new(EntityMeta::default()));
+        false
+    }
+
+    /// Returns the slot for the ID if its segment has been published.
+    pub(crate) fn slot(&self, id: u32) -> Option<&EntitySlot> {
+        let segment = (31 - (id / self.base).checked_add(1)?.leading_zeros()) as usize;
+        let (start, len) = self.segment_range(segment);
+        let slots = self.segments[segment].load(Ordering::Acquire);
+        if slots.is_null() {
+            return None;
+        }
+        debug_assert!((id as u64) >= start && (id as u64) < start + len);
+        unsafe { Some(&*slots.add((id as u64 - start) as usize)) }
+    }
+
+    pub(crate) fn slots(&self) -> impl Iterator<Item = &EntitySlot> {
+        (0..self.capacity.load(Ordering::Acquire)).map_while(|id| self.slot(id))
+    }
+
+    /// Pops the most recently freed ID.
+    pub(crate) fn pop_free(&self) -> Option<u32> {
+        let mut head = self.free_head.load(Ordering::Acquire);
+        loop {
+            let index = head as u32;
+            if index == NONE {
+                return None;
+            }
+            let next = self.slot(index)?.next_free.load(Ordering::Acquire);
+            let new_head = (head & !(u32::MAX as u64)).wrapping_add(1 << 32) | next as u64;
+            match self.free_head.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(index),
+                Err(current) => head = current,
+            }
         }
-        Self {
-            entities: new_entities.into_boxed_slice(),
-            length: AtomicU32::new(self.length.load(Ordering::Relaxed)),
-            free_list: Mutex::new(self.free_list.lock().unwrap().clone()),
-            locked: Default::default(),
+    }
+    pub(crate) fn push_free(&self, index: u32) {
+        let mut head = self.free_head.load(Ordering::Acquire);
+        loop {
+            self.slot(index)
+                .expect("Freed an entity that was never allocated")
+                .next_free
+                .store(head as u32, Ordering::Release);
+            let new_head = (head & !(u32::MAX as u64)).wrapping_add(1 << 32) | index as u64;
+            match self.free_head.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+    /// The free list in the order IDs will be handed out.
+    ///
+    /// Only exact while no other thread is allocating or freeing.
+    pub(crate) fn free_list(&self) -> Vec<u32> {
+        let mut list = Vec::new();
+        let mut index = self.free_head.load(Ordering::Acquire) as u32;
+        while index != NONE && list.len() < self.capacity.load(Ordering::Acquire) as usize {
+            let Some(slot) = self.slot(index) else {
+                break;
+            };
+            list.push(index);
+            index = slot.next_free.load(Ordering::Acquire);
+        }
+        list
+    }
+}
+
+impl Drop for EntitySetInner {
+    fn drop(&mut self) {
+        for segment in 0..MAX_SEGMENTS {
+            let slots = *self.segments[segment].get_mut();
+            if slots.is_null() {
+                break;
+            }
+            let (_, len) = self.segment_range(segment);
+            unsafe {
+                drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                    slots,
+                    len as usize,
+                )));
+            }
         }
     }
 }
 
 impl EntitySet {
-    pub fn is_locked(&self) -> bool {
-        self.0.locked.load(Ordering::Relaxed)
+    /// The number of Entity IDs that can be in use before the EntitySet has to grow.
+    pub fn capacity(&self) -> u32 {
+        self.0.capacity.load(Ordering::Acquire)
+    }
+    /// Grows the EntitySet by at least `additional` IDs. Existing clones see the new capacity.
+    ///
+    /// # Returns
+    /// False if the EntitySet can not grow that large.
+    pub fn grow(&self, additional: u32) -> bool {
+        self.0.grow(additional)
+    }
+    /// The number of IDs that have been handed out at least once.
+    pub fn allocated(&self) -> u32 {
+        self.0.length.load(Ordering::Relaxed)
+    }
+    /// The number of IDs currently in use.
+    ///
+    /// Walks the whole table, so it is only meant for diagnostics.
+    pub fn in_use(&self) -> u32 {
+        self.0
+            .slots()
+            .take(self.allocated() as usize)
+            .filter(|slot| slot.state.load(Ordering::Relaxed) & IN_USE != 0)
+            .count() as u32
     }
     pub fn entities_left(&self) -> bool {
-        self.0.entities.len() >= (self.0.length.load(Ordering::Relaxed) as usize + 1)
+        self.0.free_head.load(Ordering::Acquire) as u32 != NONE
+            || self.capacity() > self.0.length.load(Ordering::Relaxed)
+    }
+    /// Allocates an Entity. Returns None if the EntitySet is full.
+    pub fn try_alloc(&self) -> Option<Entity> {
+        let id = match self.0.pop_free() {
+            Some(id) => id,
+            None => {
+                let capacity = self.capacity();
+                self.0
+                    .length
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |length| {
+                        (length < capacity).then_some(length + 1)
+                    })
+                    .ok()?
+            }
+        };
+        let slot = self.0.slot(id)?;
+        slot.location.store(NO_LOCATION, Ordering::Release);
+        let state = slot.state.fetch_or(IN_USE, Ordering::AcqRel);
+        Some(Entity {
+            generation: generation_of(state),
+            id,
+        })
     }
     pub fn alloc(&self) -> Entity {
-        if !self.entities_left() {
-            panic!("Too many entities in the world!");
+        self.try_alloc()
+            .unwrap_or_else(|| panic!("Too many entities in the world!"))
+    }
+    /// Allocates an Entity, growing the EntitySet if it is full.
+    ///
+    /// The Entity is not placed in an Archetype until it is spawned. Safe to call from any thread.
+    pub fn reserve(&self) -> Entity {
+        loop {
+            if let Some(entity) = self.try_alloc() {
+                return entity;
+            }
+            let capacity = self.capacity() as u64;
+            if !self.0.grow_to((capacity * 2).max(1)) {
+                panic!("Too many entities in the world!");
+            }
         }
-        let id = if let Some(pop) = self.0.free_list.lock().unwrap().pop_front() {
-            pop
-        } else {
-            self.0.length.fetch_add(1, Ordering::Relaxed) as usize
-        };
-        let mut guard = self.0.entities[id as usize].lock().unwrap();
-        guard.in_use = true;
-        Entity {
-            generation: guard.generation,
-            id: id as u32,
+    }
+    /// Reserves `count` Entities, growing the EntitySet at most once.
+    pub fn reserve_many(&self, count: u32) -> Vec<Entity> {
+        let wanted = self.0.length.load(Ordering::Relaxed) as u64 + count as u64;
+        if wanted > self.capacity() as u64 {
+            self.0.grow_to(wanted.max(self.capacity() as u64 * 2));
         }
+        (0..count).map(|_| self.reserve()).collect()
     }
     pub fn push_location(&self, entity: &Entity, location: EntityLocation) {
-        if self.is_locked() {
-            panic!("EntitySet is locked!");
+        if let Some(slot) = self.0.slot(entity.id) {
+            slot.location
+                .store(pack_location(&location), Ordering::Release);
         }
-        let i = entity.id as usize;
-        let mut guard = self.0.entities[i].lock().unwrap();
-        guard.location = location;
     }
-    pub fn free<E: Into<u32>>(&self, entity: E) -> Option<EntityLocation> {
-        if self.is_locked() {
-            panic!("EntitySet is locked!");
-        }
-        let i = entity.into() as usize;
-        let mut guard = self.0.entities[i].lock().unwrap();
-        let old_location = mem::take(&mut guard.location);
-        guard.generation = NonZeroU32::new(guard.generation.get() + 1).unwrap();
-        guard.in_use = false;
-        self.0.free_list.lock().unwrap().push_back(i);
-        Some(old_location)
+    /// Frees the ID so it can be reused with the next generation.
+    ///
+    /// # Returns
+    /// None if it was not in use. Otherwise the location of the Entity, which is None if it had no components.
+    pub fn free<E: Into<u32>>(&self, entity: E) -> Option<Option<EntityLocation>> {
+        let i = entity.into();
+        let slot = self.0.slot(i)?;
+        slot.state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+                if state & IN_USE == 0 {
+                    return None;
+                }
+                let generation = (state & GENERATION_MASK) + 1;
+                Some(if generation > GENERATION_MASK {
+                    1
+                } else {
+                    generation
+                })
+            })
+            .ok()?;
+        let old_location = slot.location.swap(NO_LOCATION, Ordering::AcqRel);
+        self.0.push_free(i);
+        Some(unpack_option(old_location))
     }
+    /// Returns None if the Entity has not been placed in an Archetype.
     pub fn get_location(&self, entity: u32) -> Option<EntityLocation> {
-        if self.is_locked() {
-            panic!("EntitySet is locked!");
-        }
-        if entity as usize >= self.0.entities.len() {
-            return None;
-        }
-        let guard = self.0.entities[entity as usize].lock().unwrap();
-        Some(guard.location.clone())
+        let slot = self.0.slot(entity)?;
+        unpack_option(slot.location.load(Ordering::Acquire))
     }
-    pub fn get_entity(&self, entity: u32) -> Option<(Entity, EntityLocation)> {
-        if self.is_locked() {
-            panic!("EntitySet is locked!");
-        }
-        if entity as usize >= self.0.entities.len() {
-            return None;
-        }
-        let guard = self.0.entities[entity as usize].lock().unwrap();
-        if guard.in_use {
-            Some((
-                Entity {
-                    generation: guard.generation,
-                    id: entity,
-                },
-                guard.location.clone(),
-            ))
-        } else {
-            None
+    /// Returns true if the Entity has not been freed.
+    pub fn is_alive(&self, entity: &Entity) -> bool {
+        self.get_entity(entity.id)
+            .is_some_and(|(current, _)| current.generation == entity.generation)
+    }
+    /// Returns the current Entity for the ID and its location, or None if the ID is not in use.
+    pub fn get_entity(&self, entity: u32) -> Option<(Entity, Option<EntityLocation>)> {
+        let slot = self.0.slot(entity)?;
+        loop {
+            let state = slot.state.load(Ordering::Acquire);
+            if state & IN_USE == 0 {
+                return None;
+            }
+            let location = unpack_option(slot.location.load(Ordering::Acquire));
+            // The entity could have been freed and reallocated between the loads
+            if slot.state.load(Ordering::Acquire) == state {
+                return Some((
+                    Entity {
+                        generation: generation_of(state),
+                        id: entity,
+                    },
+                    location,
+                ));
+            }
         }
     }
 }
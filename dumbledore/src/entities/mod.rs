@@ -1,2 +1,3 @@
 pub mod entity;
+pub mod entity_map;
 pub mod entity_set;
@@ -0,0 +1,155 @@
+//! Access to the components of one Entity without naming its Bundle.
+//!
+//! ```no_run, rust, ignore
+//! let player = world.entity(&entity).unwrap();
+//! if player.has::<Health>() {
+//!     player.get_mut::<Health>()?.unwrap().health -= 1.0;
+//! }
+//! ```
+use crate::archetypes::arche::Archetype;
+use crate::archetypes::ComponentInfo;
+use crate::component::ComponentLookup;
+use crate::entities::entity::{Entity, EntityLocation};
+use crate::world::{World, WorldError};
+
+/// An Entity with its location resolved. Created with [World::entity]
+///
+/// Components are borrowed with the same locks as [Archetype::get_comp], so the World only needs to be shared.
+#[derive(Clone)]
+pub struct EntityRef<'w> {
+    world: &'w World,
+    entity: Entity,
+    location: Option<EntityLocation>,
+}
+
+/// An Entity with its location resolved that can also be despawned. Created with [World::entity_mut]
+pub struct EntityMut<'w> {
+    world: &'w mut World,
+    entity: Entity,
+    location: Option<EntityLocation>,
+}
+
+impl World {
+    /// # Returns
+    /// None if the Entity was removed or never allocated.
+    pub fn entity(&self, entity: &Entity) -> Option<EntityRef<'_>> {
+        let location = self.resolve(entity)?;
+        Some(EntityRef {
+            world: self,
+            entity: entity.clone(),
+            location,
+        })
+    }
+    /// # Returns
+    /// None if the Entity was removed or never allocated.
+    pub fn entity_mut(&mut self, entity: &Entity) -> Option<EntityMut<'_>> {
+        let location = self.resolve(entity)?;
+        Some(EntityMut {
+            world: self,
+            entity: entity.clone(),
+            location,
+        })
+    }
+    fn resolve(&self, entity: &Entity) -> Option<Option<EntityLocation>> {
+        match self.entities.get_entity(entity.id) {
+            Some((current, location)) if current == *entity => Some(location),
+            _ => None,
+        }
+    }
+}
+
+impl<'w> EntityRef<'w> {
+    pub fn entity(&self) -> &Entity {
+        &self.entity
+    }
+    /// None if the Entity has no components.
+    pub fn location(&self) -> Option<&EntityLocation> {
+        self.location.as_ref()
+    }
+    /// None if the Entity has no components.
+    pub fn archetype(&self) -> Option<&'w Archetype> {
+        self.world
+            .archetypes
+            .get(&self.location.as_ref()?.archetype)
+    }
+    /// # Returns
+    /// Ok(None) if the Entity does not have every Component.
+    ///
+    /// # Errors
+    /// [WorldError::ComponentBorrowed] if a Component is mutably borrowed or the Entity is locked.
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub fn get<T: ComponentLookup<'w>>(&self) -> Result<Option<T::RefResponse>, WorldError> {
+        let (Some(archetype), Some(location)) = (self.archetype(), self.location.as_ref()) else {
+            return Ok(None);
+        };
+        match archetype.get_comp::<T>(location.index) {
+            // The Archetype reports a borrowed Component the same way as a missing one
+            Ok(None) if self.has::<T>() => Err(WorldError::ComponentBorrowed),
+            Ok(response) => Ok(response),
+            Err(()) => Err(WorldError::ComponentBorrowed),
+        }
+    }
+    /// Marks the Components as changed at the current change tick.
+    ///
+    /// # Returns
+    /// Ok(None) if the Entity does not have every Component.
+    ///
+    /// # Errors
+    /// [WorldError::ComponentBorrowed] if a Component is borrowed or the Entity is locked.
+    /// [WorldError::DuplicateComponent] if a Component is listed twice, such as `get_mut::<(Health, Health)>()`.
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub fn get_mut<T: ComponentLookup<'w>>(&self) -> Result<Option<T::MutResponse>, WorldError> {
+        // The second borrow would fail like a borrowed Component, so this is checked first
+        let mut types = T::type_ids();
+        let len = types.len();
+        types.sort_unstable();
+        types.dedup();
+        if types.len() != len {
+            return Err(WorldError::DuplicateComponent);
+        }
+        let (Some(archetype), Some(location)) = (self.archetype(), self.location.as_ref()) else {
+            return Ok(None);
+        };
+        match archetype.get_comp_mut::<T>(location.index) {
+            // The Archetype reports a borrowed Component the same way as a missing one
+            Ok(None) if self.has::<T>() => Err(WorldError::ComponentBorrowed),
+            Ok(response) => Ok(response),
+            Err(()) => Err(WorldError::ComponentBorrowed),
+        }
+    }
+    /// True if the Entity has every Component in `T`. Does not borrow anything.
+    pub fn has<T: ComponentLookup<'w>>(&self) -> bool {
+        let components = self.components();
+        T::type_ids()
+            .iter()
+            .all(|typ| components.iter().any(|info| info.id == *typ))
+    }
+    /// The Components of the Entity, sorted by TypeId.
+    pub fn components(&self) -> &'w [ComponentInfo] {
+        self.archetype()
+            .map(|archetype| archetype.components())
+            .unwrap_or(&[])
+    }
+}
+
+impl<'w> EntityMut<'w> {
+    /// Shares the Entity. Everything but [EntityMut::despawn] is done through this.
+    pub fn as_ref(&self) -> EntityRef<'_> {
+        EntityRef {
+            world: self.world,
+            entity: self.entity.clone(),
+            location: self.location.clone(),
+        }
+    }
+    pub fn entity(&self) -> &Entity {
+        &self.entity
+    }
+    /// Removes the Entity and drops its Components.
+    ///
+    /// # Errors
+    /// [WorldError::ComponentBorrowed] if a Component is borrowed. The Entity is left as it was.
+    pub fn despawn(self) -> Result<(), WorldError> {
+        self.world.despawn_batch(&[self.entity])?;
+        Ok(())
+    }
+}
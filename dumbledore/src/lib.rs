@@ -3,10 +3,22 @@
 pub mod archetypes;
 pub mod component;
 pub mod component_ref;
+#[cfg(feature = "debug-borrows")]
+pub mod diagnostics;
 pub mod entities;
+pub mod entity_ref;
+pub mod query;
+pub mod replication;
 pub mod sets;
+pub mod snapshot;
+pub mod spatial;
+pub mod stats;
+pub mod transaction;
 pub mod world;
 
+#[cfg(test)]
+extern crate self as dumbledore;
+
 #[cfg(feature = "dumbledore-macro")]
 pub use dumbledore_macro::Bundle;
 #[cfg(feature = "dumbledore-macro")]
@@ -16,22 +28,37 @@ pub use dumbledore_macro::Component;
 pub mod tests {
     use crate::archetypes::ComponentInfo;
     use crate::component::{Bundle, Component};
+    use crate::entities::entity::{Entity, EntityLocation};
+    use crate::snapshot::binary::{BinaryRegistry, Pod, SnapshotError};
+    use crate::spatial::Spatial;
     use crate::world::World;
     use dumbledore_macro::Component;
     use std::mem;
 
-    #[derive(Debug, Clone, Component)]
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Position {
         pub x: f32,
         pub y: f32,
     }
 
-    #[derive(Debug, Clone, Component)]
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Health {
         pub health: f32,
         pub food: f32,
     }
 
+    impl Spatial for Position {
+        fn position(&self) -> [f32; 3] {
+            [self.x, self.y, 0.0]
+        }
+    }
+
+    unsafe impl Pod for Position {}
+    unsafe impl Pod for Health {}
+
+    #[derive(Clone)]
     pub struct Player {
         pub position: Position,
         pub health: Health,
@@ -42,18 +69,16 @@ pub mod tests {
         where
             Self: Sized,
         {
-            let mut position = self.position;
+            let mut position = mem::ManuallyDrop::new(self.position);
             f(
-                (&mut position as *mut Position).cast(),
+                (&mut *position as *mut Position).cast(),
                 ComponentInfo::new::<Position>(),
             );
-            mem::forget(position);
-            let mut health = self.health;
+            let mut health = mem::ManuallyDrop::new(self.health);
             f(
-                (&mut health as *mut Health).cast(),
+                (&mut *health as *mut Health).cast(),
                 ComponentInfo::new::<Health>(),
             );
-            mem::forget(health);
         }
 
         fn component_info() -> Vec<ComponentInfo>
@@ -74,20 +99,26 @@ pub mod tests {
         }
     }
 
+    /// A player at `(i, 0)` with full health and food.
+    pub fn player(i: usize) -> Player {
+        Player {
+            position: Position {
+                x: i as f32,
+                y: 0.0,
+            },
+            health: Health {
+                health: 100.0,
+                food: 100.0,
+            },
+        }
+    }
+
     #[test]
     pub fn test() {
         let mut world = World::new(256);
         world.add_archetype::<Player>(256);
         for _ in 0..255 {
-            world
-                .add_entity(Player {
-                    position: Position { x: 0.0, y: 0.0 },
-                    health: Health {
-                        health: 100.0,
-                        food: 100.0,
-                    },
-                })
-                .unwrap();
+            world.add_entity(player(0)).unwrap();
         }
         let player = world.get_archetype::<Player>().unwrap();
         let now = std::time::Instant::now();
@@ -110,21 +141,14 @@ pub mod tests {
             if !world.get_entities().entities_left() {
                 world.increase_entities(Some(256)).unwrap();
             }
-            if let Err(error) = world.add_entity(Player {
-                position: Position { x: 0.0, y: 0.0 },
-                health: Health {
-                    health: 100.0,
-                    food: 100.0,
-                },
-            }) {
-                match error {
-                    crate::world::WorldError::TooManyEntitiesInArchetype => {
-                        let option = world.take_archetype::<Player>().unwrap();
-                        let archetype = option.resize(Some(256)).unwrap();
-                        world.push_archetype::<Player>(archetype);
-                    }
-                    _ => {}
-                }
+            if let Err(crate::world::WorldError::TooManyEntitiesInArchetype) =
+                world.add_entity(player(0))
+            {
+                let option = world.take_archetype::<Player>().unwrap();
+                let archetype = option.resize(Some(256)).unwrap();
+                world.push_archetype::<Player>(archetype);
+                // Unallocated IDs no longer have a location, so the entity has to be added after the resize
+                world.add_entity(player(0)).unwrap();
             }
         }
         let player = world.get_archetype::<Player>().unwrap();
@@ -146,46 +170,1541 @@ pub mod tests {
             if !world.get_entities().entities_left() {
                 world.increase_entities(Some(256)).unwrap();
             }
-            if let Err(error) = world.add_entity(Player {
-                position: Position { x: 0.0, y: 0.0 },
-                health: Health {
-                    health: 100.0,
-                    food: 100.0,
-                },
-            }) {
-                match error {
-                    crate::world::WorldError::TooManyEntitiesInArchetype => {
-                        let option = world.take_archetype::<Player>().unwrap();
-                        let archetype = option.resize(Some(256)).unwrap();
-                        world.push_archetype::<Player>(archetype);
-                    }
-                    _ => {}
-                }
+            if let Err(crate::world::WorldError::TooManyEntitiesInArchetype) =
+                world.add_entity(player(0))
+            {
+                let option = world.take_archetype::<Player>().unwrap();
+                let archetype = option.resize(Some(256)).unwrap();
+                world.push_archetype::<Player>(archetype);
             }
         }
         for _ in 0..256 {
             let random1: u8 = rand::random();
             let entity = crate::entities::entity::Entity::from(random1 as u32);
 
-            world.remove_entity(entity);
-            let player = world.get_archetype::<Player>().unwrap();
+            world.remove_entity(entity).unwrap();
+            let archetype = world.get_archetype::<Player>().unwrap();
 
             let (entity, id) = world
                 .add_entity(Player {
-                    position: Position { x: 0.0, y: 0.0 },
                     health: Health {
                         health: 100.0,
                         food: 50.0,
                     },
+                    ..player(0)
                 })
                 .unwrap();
 
             assert_eq!(entity.id, random1 as u32);
 
-            if player.get_comp::<Health>(id.index).is_err() {
+            if archetype.get_comp::<Health>(id.index).is_err() {
                 println!(" {:?}", id);
                 println!(" {:?}", entity);
             };
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn serde_snapshot() {
+        use crate::entities::entity::Entity;
+        use crate::entities::entity_map::{EntityMap, MapEntities};
+        use crate::snapshot::serialize::SerdeRegistry;
+        use dumbledore_macro::Bundle;
+
+        #[derive(Debug, Clone, PartialEq, Component, serde::Serialize, serde::Deserialize)]
+        pub struct Target(Entity);
+
+        impl MapEntities for Target {
+            fn map_entities(&mut self, map: &EntityMap) {
+                self.0 = map.map(&self.0);
+            }
+        }
+
+        #[derive(Bundle)]
+        pub struct Follower {
+            pub position: Position,
+            pub target: Target,
+        }
+
+        let mut registry = SerdeRegistry::new();
+        registry
+            .register::<Position>("position")
+            .register::<Health>("health")
+            .register_mapped::<Target>("target");
+
+        let mut world = World::new(16);
+        world.add_archetype::<Player>(16);
+        world.add_archetype::<Follower>(16);
+        let mut players = Vec::new();
+        for i in 0..4 {
+            let (player, _) = world
+                .add_entity(Player {
+                    health: Health {
+                        health: 100.0,
+                        food: i as f32,
+                    },
+                    ..player(i)
+                })
+                .unwrap();
+            players.push(player);
+        }
+        let (follower, _) = world
+            .add_entity(Follower {
+                position: Position { x: 1.0, y: 2.0 },
+                target: Target(players[2].clone()),
+            })
+            .unwrap();
+        world.remove_entity(players[0].clone()).unwrap();
+
+        let mut json = Vec::new();
+        world
+            .serialize(&mut serde_json::Serializer::new(&mut json), &registry)
+            .unwrap();
+
+        // Offsets the IDs so the Entities have to be remapped
+        let mut loaded = World::new(2);
+        loaded.add_archetype::<Player>(2);
+        for _ in 0..2 {
+            loaded.add_entity(player(0)).unwrap();
+        }
+        let map = loaded
+            .deserialize(&mut serde_json::Deserializer::from_slice(&json), &registry)
+            .unwrap();
+        assert_eq!(map.len(), 4);
+        assert!(map.get(&players[0]).is_none());
+
+        for (i, player) in players.iter().enumerate().skip(1) {
+            let new_player = map.get(player).unwrap();
+            assert_ne!(new_player, player);
+            let location = loaded.get_entities().get_location(new_player.id).unwrap();
+            let archetype = loaded.get_archetype::<Player>().unwrap();
+            let (position, health) = archetype
+                .get_comp::<(Position, Health)>(location.index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(position.as_ref().x, i as f32);
+            assert_eq!(health.as_ref().food, i as f32);
+        }
+        let new_follower = map.get(&follower).unwrap();
+        let location = loaded.get_entities().get_location(new_follower.id).unwrap();
+        let target = loaded
+            .get_archetype::<Follower>()
+            .unwrap()
+            .get_comp::<Target>(location.index)
+            .unwrap()
+            .unwrap();
+        assert_eq!(&target.as_ref().0, map.get(&players[2]).unwrap());
+    }
+
+    #[test]
+    pub fn binary_snapshot() {
+        let mut registry = BinaryRegistry::new();
+        registry
+            .register::<Position>("position")
+            .register::<Health>("health");
+
+        let mut world = World::new(32);
+        world.add_archetype::<Player>(32);
+        for i in 0..20 {
+            world
+                .add_entity(Player {
+                    health: Health {
+                        health: 100.0,
+                        food: i as f32,
+                    },
+                    ..player(i)
+                })
+                .unwrap();
+        }
+        for id in [3u32, 11, 7] {
+            world.remove_entity(id).unwrap();
+        }
+
+        let mut bytes = Vec::new();
+        world.write_binary(&mut bytes, &registry).unwrap();
+        let loaded = World::read_binary(bytes.as_slice(), &registry).unwrap();
+        let valid = bytes.clone();
+
+        for id in 0..32 {
+            assert_eq!(
+                world.get_entities().get_entity(id),
+                loaded.get_entities().get_entity(id)
+            );
+            let Some((_, Some(location))) = loaded.get_entities().get_entity(id) else {
+                continue;
+            };
+            let (position, health) = loaded
+                .get_archetype::<Player>()
+                .unwrap()
+                .get_comp::<(Position, Health)>(location.index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(position.as_ref().x, id as f32);
+            assert_eq!(health.as_ref().food, id as f32);
+        }
+        // Free lists are kept so both worlds hand out the same IDs
+        for _ in 0..4 {
+            assert_eq!(
+                world.add_entity(player(0)).unwrap(),
+                loaded.add_entity(player(0)).unwrap()
+            );
+        }
+
+        let last = bytes.len() - 5;
+        bytes[last] ^= 0xFF;
+        assert!(matches!(
+            World::read_binary(bytes.as_slice(), &registry),
+            Err(SnapshotError::ChecksumMismatch)
+        ));
+
+        // Free lists that would hand out a slot twice are rejected even with a valid checksum
+        let corrupt = |section: usize, at: usize, value: &[u8]| {
+            let mut bytes = valid.clone();
+            let mut offset = 6;
+            for _ in 0..section {
+                offset +=
+                    12 + u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize;
+            }
+            let len = u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize;
+            let payload = offset + 8..offset + 8 + len;
+            bytes[payload.start + at..payload.start + at + value.len()].copy_from_slice(value);
+            let crc = crate::snapshot::binary::crc32(&bytes[payload.clone()]);
+            bytes[payload.end..payload.end + 4].copy_from_slice(&crc.to_le_bytes());
+            World::read_binary(bytes.as_slice(), &registry)
+        };
+        assert!(corrupt(0, 0, &[]).is_ok());
+        let u32_at = |at: usize| u32::from_le_bytes(valid[at..at + 4].try_into().unwrap());
+        // Header: count, id, capacity, entities_len, free_len, free entries
+        let header = 6 + 8;
+        assert!(u32_at(header + 16) >= 2);
+        let first = u32_at(header + 20).to_le_bytes();
+        assert!(matches!(
+            corrupt(0, 24, &first),
+            Err(SnapshotError::Corrupt)
+        ));
+        // Table: capacity, length, free_len, free entries
+        let table = header + u32_at(6) as usize + 4 + 8;
+        assert!(u32_at(table + 8) >= 2);
+        let first = u32_at(table + 12).to_le_bytes();
+        assert!(matches!(
+            corrupt(1, 16, &first),
+            Err(SnapshotError::Corrupt)
+        ));
+        // Entity 0 is alive
+        assert!(matches!(
+            corrupt(1, 12, &0u32.to_le_bytes()),
+            Err(SnapshotError::Corrupt)
+        ));
+
+        #[derive(Debug, Clone, Copy, Component)]
+        struct Tag;
+        unsafe impl Pod for Tag {}
+        #[derive(dumbledore_macro::Bundle)]
+        struct Tagged {
+            position: Position,
+            tag: Tag,
+        }
+        registry.register::<Tag>("tag");
+        let mut world = World::new(4);
+        world
+            .spawn_batch((0..3).map(|i| Tagged {
+                position: Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                tag: Tag,
+            }))
+            .unwrap();
+        let mut bytes = Vec::new();
+        world.write_binary(&mut bytes, &registry).unwrap();
+        let loaded = World::read_binary(bytes.as_slice(), &registry).unwrap();
+        let archetype = loaded.get_archetype::<Tagged>().unwrap();
+        let (position, _) = archetype.get_comp::<(Position, Tag)>(2).unwrap().unwrap();
+        assert_eq!(position.x, 2.0);
+    }
+
+    #[test]
+    pub fn replication() {
+        use crate::entities::entity_map::EntityMap;
+        use crate::replication::client::ReplicaApplier;
+        use crate::replication::packet::{DeltaPacket, EntityUpdate};
+        use crate::replication::server::ClientReplicator;
+        use crate::replication::{ComponentCodec, PodCodec, ReplicationError, ReplicationRegistry};
+
+        struct PositionCodec;
+
+        impl ComponentCodec for PositionCodec {
+            type Component = Position;
+
+            fn encode(&self, component: &Position, out: &mut Vec<u8>) {
+                out.extend_from_slice(&component.x.to_le_bytes());
+                out.extend_from_slice(&component.y.to_le_bytes());
+            }
+
+            fn decode(&self, bytes: &[u8], _map: &EntityMap) -> Result<Position, ReplicationError> {
+                if bytes.len() != 8 {
+                    return Err(ReplicationError::Malformed);
+                }
+                Ok(Position {
+                    x: f32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+                    y: f32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+                })
+            }
+        }
+
+        let mut registry = ReplicationRegistry::new();
+        registry
+            .register(PositionCodec)
+            .register(PodCodec::<Health>::default());
+
+        let mut server = World::new(16);
+        server.add_archetype::<Player>(16);
+        let players = (0..3)
+            .map(|i| server.add_entity(player(i)).unwrap().0)
+            .collect::<Vec<_>>();
+        let mut client = World::new(1);
+        // The client uses the id of the server Archetype for something else
+        #[derive(dumbledore_macro::Bundle)]
+        #[bundle(id = 0)]
+        struct Marker {
+            _position: Position,
+        }
+        client.add_archetype::<Marker>(1);
+        let mut replicator = ClientReplicator::new();
+        let mut applier = ReplicaApplier::new();
+        let mut send =
+            |applier: &mut ReplicaApplier, server: &World, client: &mut World, interest: &[_]| {
+                let packet = replicator.encode_delta(server, &registry, interest.iter().cloned());
+                server.increment_change_tick();
+                let mut bytes = Vec::new();
+                packet.write(&mut bytes);
+                let packet = DeltaPacket::read(&bytes).unwrap();
+                applier.apply(client, &registry, &packet).unwrap();
+                (packet, applier.entity_map().clone())
+            };
+
+        let (packet, map) = send(&mut applier, &server, &mut client, &players[1..]);
+        assert_eq!(packet.spawned.len(), 2);
+        assert!(map.get(&players[0]).is_none());
+        let read_position = |client: &World, map: &EntityMap, player| {
+            let local: &crate::entities::entity::Entity = map.get(player).unwrap();
+            let location = client.get_entities().get_location(local.id).unwrap();
+            let archetype = &client.archetypes[&location.archetype];
+            let (position, health) = archetype
+                .get_comp::<(Position, Health)>(location.index)
+                .unwrap()
+                .unwrap();
+            (*position.as_ref(), *health.as_ref())
+        };
+        assert_eq!(read_position(&client, &map, &players[2]).0.x, 2.0);
+
+        let (packet, _) = send(&mut applier, &server, &mut client, &players[1..]);
+        assert!(packet.is_empty());
+
+        let location = server.get_entities().get_location(players[1].id).unwrap();
+        server
+            .get_archetype::<Player>()
+            .unwrap()
+            .get_comp_mut::<Health>(location.index)
+            .unwrap()
+            .unwrap()
+            .as_mut()
+            .food = 5.0;
+        let (packet, map) = send(&mut applier, &server, &mut client, &players[1..]);
+        assert_eq!(packet.changed.len(), 1);
+        assert_eq!(packet.changed[0].components.len(), 1);
+        assert_eq!(read_position(&client, &map, &players[1]).1.food, 5.0);
+        let local = client
+            .get_entities()
+            .get_location(map.get(&players[1]).unwrap().id);
+        assert_ne!(local.unwrap().archetype, 0);
+
+        // A packet with a component that does not decode changes nothing
+        let malformed = DeltaPacket {
+            tick: server.change_tick(),
+            despawned: vec![players[1].clone()],
+            spawned: vec![EntityUpdate {
+                entity: players[0].clone(),
+                archetype: 0,
+                components: vec![(0, vec![0; 3])],
+            }],
+            ..DeltaPacket::default()
+        };
+        let in_use = client.get_entities().in_use();
+        assert_eq!(
+            applier.apply(&mut client, &registry, &malformed),
+            Err(ReplicationError::Malformed)
+        );
+        assert_eq!(client.get_entities().in_use(), in_use);
+        assert_eq!(applier.entity_map().len(), 2);
+        assert_eq!(read_position(&client, &map, &players[1]).1.food, 5.0);
+
+        server.remove_entity(players[2].clone()).unwrap();
+        let (packet, map) = send(&mut applier, &server, &mut client, &players[1..]);
+        assert_eq!(packet.despawned, vec![players[2].clone()]);
+        assert!(map.get(&players[2]).is_none());
+        assert_eq!(map.len(), 1);
+
+        // A local Entity that was already despawned is only dropped from the map
+        let local = map.get(&players[1]).unwrap().clone();
+        client.despawn_batch(&[local]).unwrap();
+        server.remove_entity(players[1].clone()).unwrap();
+        let (packet, map) = send(&mut applier, &server, &mut client, &players[1..]);
+        assert_eq!(packet.despawned, vec![players[1].clone()]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    pub fn interest_management() {
+        use crate::replication::interest::{InterestEvent, InterestManager};
+
+        let mut world = World::new(16);
+        world.add_archetype::<Player>(16);
+        let players = [0, 3, 10]
+            .into_iter()
+            .map(|x| world.add_entity(player(x)).unwrap().0)
+            .collect::<Vec<_>>();
+        let mut interest = InterestManager::<Position>::new(4.0);
+        interest.add_observer(players[0].clone(), 5.0);
+        let enter = |entity: &crate::entities::entity::Entity| InterestEvent::Enter {
+            observer: players[0].clone(),
+            entity: entity.clone(),
+        };
+        let leave = |entity: &crate::entities::entity::Entity| InterestEvent::Leave {
+            observer: players[0].clone(),
+            entity: entity.clone(),
+        };
+
+        assert_eq!(interest.update(&world), vec![enter(&players[1])]);
+        assert!(interest.update(&world).is_empty());
+
+        let location = world.get_entities().get_location(players[2].id).unwrap();
+        world
+            .get_archetype::<Player>()
+            .unwrap()
+            .get_comp_mut::<Position>(location.index)
+            .unwrap()
+            .unwrap()
+            .as_mut()
+            .x = -4.0;
+        assert_eq!(interest.update(&world), vec![enter(&players[2])]);
+
+        world.remove_entity(players[1].clone()).unwrap();
+        assert_eq!(interest.update(&world), vec![leave(&players[1])]);
+        assert_eq!(
+            interest.interest(&players[0]).collect::<Vec<_>>(),
+            vec![&players[2]]
+        );
+
+        world.remove_entity(players[0].clone()).unwrap();
+        assert_eq!(interest.update(&world), vec![leave(&players[2])]);
+        assert_eq!(interest.observers().count(), 0);
+    }
+
+    #[test]
+    pub fn spatial_queries() {
+        let mut world = World::new(16);
+        world.add_archetype::<Player>(16);
+        world.set_spatial_index::<Position>(4.0);
+        let players = (0..=10)
+            .chain([1_000_000])
+            .map(|x| world.add_entity(player(x)).unwrap().0)
+            .collect::<Vec<_>>();
+        assert!(world.sync_spatial_index().unwrap().is_empty());
+
+        let mut near = world.query_radius([0.0, 0.0, 0.0], 2.5).unwrap();
+        near.sort_by_key(|e| e.id);
+        assert_eq!(near, players[0..3]);
+        let mut inside = world
+            .query_aabb([3.5, -1.0, -1.0], [5.0, 1.0, 1.0])
+            .unwrap();
+        inside.sort_by_key(|e| e.id);
+        assert_eq!(inside, players[4..6]);
+        assert_eq!(world.nearest_k([9.4, 0.0, 0.0], 2).unwrap(), players[9..11]);
+        assert_eq!(
+            world.nearest_k([2.0e6, 0.0, 0.0], 1).unwrap(),
+            players[11..12]
+        );
+        assert!(world.query_aabb([5.0; 3], [0.0; 3]).unwrap().is_empty());
+        let mut all = world
+            .query_aabb([f32::NEG_INFINITY; 3], [f32::INFINITY; 3])
+            .unwrap();
+        all.sort_by_key(|e| e.id);
+        assert_eq!(all, players);
+
+        world.remove_entity(players[0].clone()).unwrap();
+        let location = world.get_entities().get_location(players[10].id).unwrap();
+        world
+            .get_archetype::<Player>()
+            .unwrap()
+            .get_comp_mut::<Position>(location.index)
+            .unwrap()
+            .unwrap()
+            .as_mut()
+            .x = -1.0;
+        assert_eq!(
+            world.sync_spatial_index().unwrap(),
+            vec![players[0].clone()]
+        );
+        let mut near = world.query_radius([0.0, 0.0, 0.0], 1.5).unwrap();
+        near.sort_by_key(|e| e.id);
+        assert_eq!(near, vec![players[1].clone(), players[10].clone()]);
+
+        // Syncing leaves the tick alone and still sees a second change in the same tick
+        let tick = world.change_tick();
+        world
+            .get_archetype::<Player>()
+            .unwrap()
+            .get_comp_mut::<Position>(location.index)
+            .unwrap()
+            .unwrap()
+            .as_mut()
+            .x = 20.0;
+        assert!(world.sync_spatial_index().unwrap().is_empty());
+        assert_eq!(world.change_tick(), tick);
+        assert_eq!(
+            world.nearest_k([20.0, 0.0, 0.0], 1).unwrap(),
+            vec![players[10].clone()]
+        );
+    }
+
+    #[test]
+    pub fn concurrent_entity_alloc() {
+        use std::collections::HashSet;
+
+        let world = World::new(4096);
+        let entities = world.get_entities();
+        let allocated = std::thread::scope(|scope| {
+            let handles = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut allocated = Vec::new();
+                        for i in 0..512 {
+                            let entity = entities.alloc();
+                            if i % 2 == 0 {
+                                assert!(entities.free(entity.id).is_some());
+                            } else {
+                                allocated.push(entity);
+                            }
+                        }
+                        allocated
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(allocated.len(), 8 * 256);
+        let ids = allocated.iter().map(|e| e.id).collect::<HashSet<_>>();
+        assert_eq!(ids.len(), allocated.len());
+        assert!(allocated.iter().all(|entity| entities.is_alive(entity)));
+        assert!(entities.try_alloc().is_some());
+    }
+
+    #[test]
+    pub fn grow_entities_keeps_handles() {
+        let mut world = World::new(4);
+        let entities = world.get_entities().clone();
+        let first = (0..4).map(|_| entities.alloc()).collect::<Vec<_>>();
+        assert!(entities.try_alloc().is_none());
+
+        world.increase_entities(None).unwrap();
+        assert_eq!(entities.capacity(), world.get_entities().capacity());
+        assert!(entities.capacity() >= 8);
+
+        // The old clone keeps working after the world grew
+        let entity = entities.alloc();
+        let location = EntityLocation {
+            archetype: 0,
+            index: 7,
+        };
+        entities.push_location(&entity, location.clone());
+        assert_eq!(world.get_entities().get_location(entity.id), Some(location));
+        assert!(first.iter().all(|e| entities.is_alive(e)));
+        assert!(entities.free(first[0].id).is_some());
+
+        // Growing from a clone while other threads allocate
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..64 {
+                        while entities.try_alloc().is_none() {
+                            entities.grow(16);
+                        }
+                    }
+                });
+            }
+        });
+        assert!(entities.capacity() >= 4 + 1 + 256);
+    }
+
+    #[test]
+    pub fn reserve_entities() {
+        let mut world = World::new(2);
+        let reserved = std::thread::scope(|scope| {
+            let world = &world;
+            let handles = (0..4)
+                .map(|_| scope.spawn(move || world.reserve_entities(8)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(reserved.len(), 32);
+        let single = world.reserve_entity();
+        assert!(world.get_entities().is_alive(&single));
+        assert_eq!(world.get_entities().get_location(single.id), None);
+
+        for (i, entity) in reserved.iter().enumerate().filter(|(i, _)| i % 2 == 0) {
+            world.spawn_reserved(entity, player(i));
+        }
+        // Nothing is placed until the flush
+        assert!(world.get_archetype::<Player>().is_none());
+        assert!(world.flush().is_empty());
+
+        let player = world.get_archetype::<Player>().unwrap();
+        for (i, entity) in reserved.iter().enumerate() {
+            let location = world.get_entities().get_location(entity.id);
+            if i % 2 == 1 {
+                assert_eq!(location, None);
+                continue;
+            }
+            let position = player
+                .get_comp::<Position>(location.unwrap().index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(position.as_ref().x, i as f32);
+        }
+        world.remove_entity(reserved[1].id).unwrap();
+        world.remove_entity(reserved[2].id).unwrap();
+        assert!(!world.get_entities().is_alive(&reserved[2]));
+    }
+
+    #[test]
+    pub fn empty_entities() {
+        let mut world = World::new(4);
+        let empty = world.reserve_entity();
+        assert_eq!(world.is_empty(&empty), Ok(true));
+        assert_eq!(world.get_entities().get_location(empty.id), None);
+        assert_eq!(
+            world.get_entities().get_entity(empty.id),
+            Some((empty.clone(), None))
+        );
+
+        let mut registry = BinaryRegistry::new();
+        registry.register::<Position>("position");
+        registry.register::<Health>("health");
+        let mut bytes = Vec::new();
+        world.write_binary(&mut bytes, &registry).unwrap();
+        let loaded = World::read_binary(bytes.as_slice(), &registry).unwrap();
+        assert_eq!(loaded.is_empty(&empty), Ok(true));
+
+        let player = Player {
+            position: Position { x: 1.0, y: 2.0 },
+            ..player(0)
+        };
+        let location = world.insert(&empty, player.clone()).unwrap();
+        assert_eq!(world.is_empty(&empty), Ok(false));
+        assert_eq!(
+            world.get_entities().get_location(empty.id),
+            Some(location.clone())
+        );
+        assert_eq!(
+            world.insert(&empty, player),
+            Err(crate::world::WorldError::EntityNotEmpty)
+        );
+        let position = world
+            .get_archetype::<Player>()
+            .unwrap()
+            .get_comp::<Position>(location.index)
+            .unwrap()
+            .unwrap();
+        assert_eq!(position.as_ref(), &Position { x: 1.0, y: 2.0 });
+        drop(position);
+
+        let other = world.reserve_entity();
+        world.remove_entity(other.id).unwrap();
+        assert_eq!(
+            world.is_empty(&other),
+            Err(crate::world::WorldError::EntityNotFound)
+        );
+    }
+
+    #[test]
+    pub fn batch_spawn_despawn() {
+        let mut world = World::new(16);
+        let entities = world.spawn_batch((0..1000).map(player)).unwrap();
+        assert_eq!(entities.len(), 1000);
+        let archetype = world.get_archetype::<Player>().unwrap();
+        for (i, entity) in entities.iter().enumerate() {
+            let location = world.get_entities().get_location(entity.id).unwrap();
+            let position = archetype
+                .get_comp::<Position>(location.index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(position.as_ref().x, i as f32);
+        }
+
+        // A borrowed entity keeps the whole batch from being removed
+        let archetype = archetype.clone();
+        let location = world.get_entities().get_location(entities[1].id).unwrap();
+        let borrowed = archetype.get_comp::<Position>(location.index).unwrap();
+        assert_eq!(
+            world.despawn_batch(&entities[..2]),
+            Err(crate::world::WorldError::ComponentBorrowed)
+        );
+        assert!(entities[..2]
+            .iter()
+            .all(|e| world.get_entities().is_alive(e)));
+        assert!(archetype.get_comp::<Position>(0).unwrap().is_some());
+        drop(borrowed);
+        drop(archetype);
+
+        let despawned = entities.iter().step_by(2).cloned().collect::<Vec<_>>();
+        assert_eq!(world.despawn_batch(&despawned), Ok(500));
+        // Already removed entities are skipped
+        assert_eq!(world.despawn_batch(&despawned[..10]), Ok(0));
+        assert!(despawned.iter().all(|e| !world.get_entities().is_alive(e)));
+
+        // The freed slots are reused by the next batch
+        let respawned = world.spawn_batch((1000..1500).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap();
+        assert_eq!(
+            archetype
+                .0
+                .entities_len
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1000
+        );
+        for (i, entity) in respawned.iter().enumerate() {
+            let location = world.get_entities().get_location(entity.id).unwrap();
+            let position = archetype
+                .get_comp::<Position>(location.index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(position.as_ref().x, (1000 + i) as f32);
+        }
+    }
+
+    #[test]
+    pub fn dense_archetype() {
+        let mut world = World::new(64);
+        world.add_dense_archetype::<Player>(64);
+        let entities = world.spawn_batch((0..32).map(player)).unwrap();
+        world.remove_entity(entities[3].id).unwrap();
+        world
+            .despawn_batch(&[
+                entities[0].clone(),
+                entities[31].clone(),
+                entities[30].clone(),
+            ])
+            .unwrap();
+
+        let archetype = world.get_archetype::<Player>().unwrap();
+        assert!(archetype.is_dense());
+        let len = archetype
+            .0
+            .entities_len
+            .load(std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(len, 28);
+        assert!(archetype.0.free_list.lock().unwrap().is_empty());
+        // Every index below the length is live and the moved entities point at their new slots
+        for index in 0..len {
+            let id = archetype.0.entity_data[index as usize]
+                .entity_id
+                .load(std::sync::atomic::Ordering::Relaxed);
+            let location = world.get_entities().get_location(id).unwrap();
+            assert_eq!(location.index, index);
+            let position = archetype.get_comp::<Position>(index).unwrap().unwrap();
+            assert_eq!(position.as_ref().x, id as f32);
+        }
+
+        // A borrowed last entity keeps the removal from moving it. Nothing is freed in that case
+        let archetype = archetype.clone();
+        let borrowed = archetype.get_comp::<Position>(len - 1).unwrap().unwrap();
+        assert_eq!(
+            world.remove_entity(entities[5].id),
+            Err(crate::world::WorldError::ComponentBorrowed)
+        );
+        assert!(world.get_entities().is_alive(&entities[5]));
+        drop(borrowed);
+        world.remove_entity(entities[5].id).unwrap();
+        assert_eq!(
+            world.remove_entity(entities[5].id),
+            Err(crate::world::WorldError::EntityNotFound)
+        );
+        assert!(!world.get_entities().is_alive(&entities[5]));
+        assert_eq!(archetype.len(), 27);
+        drop(archetype);
+
+        // Removing from a taken Archetype is an error instead of a panic
+        let taken = world.take_archetype::<Player>().unwrap();
+        assert_eq!(
+            world.remove_entity(entities[6].id),
+            Err(crate::world::WorldError::ArchetypeNotFound)
+        );
+        assert_eq!(
+            world.despawn_batch(&entities[6..7]),
+            Err(crate::world::WorldError::ArchetypeNotFound)
+        );
+        world.push_archetype::<Player>(taken);
+        assert!(world.get_entities().is_alive(&entities[6]));
+
+        let mut registry = BinaryRegistry::new();
+        registry.register::<Position>("position");
+        registry.register::<Health>("health");
+        let mut bytes = Vec::new();
+        world.write_binary(&mut bytes, &registry).unwrap();
+        let loaded = World::read_binary(bytes.as_slice(), &registry).unwrap();
+        assert!(loaded.get_archetype::<Player>().unwrap().is_dense());
+
+        // Removing through the Archetype refuses slots that were already removed
+        let mut world = World::new(4);
+        world.add_archetype::<Player>(4);
+        world.spawn_batch((0..3).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap();
+        assert_eq!(archetype.swap_remove(0), Ok(Some(2)));
+        assert_eq!(archetype.swap_remove(1), Ok(None));
+        assert_eq!(archetype.swap_remove(1), Err(()));
+        archetype.remove(0).unwrap();
+        assert_eq!(archetype.remove(0), Err(()));
+        assert_eq!(archetype.swap_remove(0), Err(()));
+        assert!(archetype.is_empty());
+    }
+
+    #[test]
+    pub fn shrink_archetypes() {
+        let mut world = World::new(16);
+        let entities = world.spawn_batch((0..1000).map(player)).unwrap();
+        let survivors = entities.iter().step_by(10).cloned().collect::<Vec<_>>();
+        let gone = entities
+            .iter()
+            .filter(|e| e.id % 10 != 0)
+            .cloned()
+            .collect::<Vec<_>>();
+        world.despawn_batch(&gone).unwrap();
+
+        // A borrowed entity keeps the archetype from moving
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+        let location = world.get_entities().get_location(survivors[1].id).unwrap();
+        let borrowed = archetype.get_comp::<Position>(location.index).unwrap();
+        assert_eq!(world.shrink_to_fit(), 0);
+        drop(borrowed);
+        drop(archetype);
+
+        assert!(world.shrink_archetype::<Player>().unwrap() > 0);
+        let archetype = world.get_archetype::<Player>().unwrap();
+        assert_eq!(archetype.0.entity_data.len(), survivors.len());
+        for entity in survivors.iter() {
+            let location = world.get_entities().get_location(entity.id).unwrap();
+            let position = archetype
+                .get_comp::<Position>(location.index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(position.as_ref().x, entity.id as f32);
+        }
+        // Nothing left to reclaim and the archetype still grows afterwards
+        assert_eq!(world.shrink_to_fit(), 0);
+        let more = world.spawn_batch((0..10).map(player)).unwrap();
+
+        // Shrinking an empty archetype releases all of its data
+        world.despawn_batch(&survivors).unwrap();
+        world.despawn_batch(&more).unwrap();
+        assert!(world.shrink_to_fit() > 0);
+        assert_eq!(
+            world.get_archetype::<Player>().unwrap().0.entity_data.len(),
+            0
+        );
+        world.spawn_batch((0..10).map(player)).unwrap();
+    }
+
+    #[test]
+    pub fn world_stats() {
+        let mut world = World::new(64);
+        world.add_archetype::<Player>(128);
+        let entities = world.spawn_batch((0..100).map(player)).unwrap();
+        world.despawn_batch(&entities[..40]).unwrap();
+        world.reserve_entity();
+
+        let stats = world.stats();
+        assert_eq!(stats.archetypes.len(), 1);
+        let player = &stats.archetypes[0];
+        assert_eq!(player.id, 0);
+        assert_eq!(player.capacity, 128);
+        assert_eq!(player.live, 60);
+        assert_eq!(player.free_list, 40);
+        assert!(!player.dense);
+        assert!(
+            player.bytes_allocated >= 128 * (mem::size_of::<Position>() + mem::size_of::<Health>())
+        );
+        let mut names = player.components.clone();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                std::any::type_name::<Health>(),
+                std::any::type_name::<Position>()
+            ]
+        );
+
+        // The reserved entity reuses a freed ID
+        assert_eq!(stats.entities.allocated, 100);
+        assert_eq!(stats.entities.in_use, 61);
+        assert_eq!(stats.entities.free_list, 39);
+        assert!(stats.entities.capacity >= 100);
+        assert!(stats.entities.occupancy() > 0.0 && stats.entities.occupancy() <= 1.0);
+        assert_eq!(stats.bytes_allocated(), player.bytes_allocated);
+    }
+
+    #[test]
+    pub fn query_for_each() {
+        let mut world = World::new(16);
+        let entities = world.spawn_batch((0..5000).map(player)).unwrap();
+        world.despawn_batch(&entities[..100]).unwrap();
+
+        // A borrowed entity is skipped instead of aliased
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+        let location = world.get_entities().get_location(entities[100].id).unwrap();
+        let borrowed = archetype.get_comp::<Health>(location.index).unwrap();
+
+        let visited = world
+            .query::<(Position, Health)>()
+            .for_each(|entity, (mut position, _)| {
+                assert_eq!(position.as_ref().x, entity.id as f32);
+                position.as_mut().y = 1.0;
+            });
+        assert_eq!(visited, 4899);
+        drop(borrowed);
+
+        #[cfg(feature = "rayon")]
+        {
+            let visited = world
+                .query::<(Position, Health)>()
+                .chunk_size(64)
+                .par_for_each(|_, (mut position, mut health)| {
+                    position.as_mut().y += 1.0;
+                    health.as_mut().health -= 1.0;
+                });
+            assert_eq!(visited, 4900);
+        }
+        let expected = if cfg!(feature = "rayon") { 2.0 } else { 1.0 };
+        let mut seen = 0;
+        world.query::<Position>().for_each(|entity, position| {
+            let y = if entity.id == entities[100].id {
+                expected - 1.0
+            } else {
+                expected
+            };
+            assert_eq!(position.as_ref().y, y);
+            seen += 1;
+        });
+        assert_eq!(seen, 4900);
+        #[derive(Component)]
+        struct Velocity;
+        assert_eq!(world.query::<(Position, Velocity)>().for_each(|_, _| {}), 0);
+    }
+
+    #[test]
+    pub fn query_columns() {
+        use crate::world::WorldError;
+
+        let mut world = World::new(16);
+        let entities = world.spawn_batch((0..2000).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+        let index = world
+            .get_entities()
+            .get_location(entities[7].id)
+            .unwrap()
+            .index;
+
+        let visited = world
+            .query::<(Position, Health)>()
+            .columns_mut(|entity, (position, health)| {
+                assert_eq!(position.x, entity.id as f32);
+                position.y = 1.0;
+                health.food -= 1.0;
+                // Per entity borrows conflict with the column lock
+                if entity == entities[7] {
+                    assert!(archetype.get_comp::<Position>(index).unwrap().is_none());
+                }
+            })
+            .unwrap();
+        assert_eq!(visited, 2000);
+
+        // Shared column locks allow shared per entity borrows, but not mutable ones
+        world
+            .query::<Position>()
+            .columns(|entity, position| {
+                assert_eq!(position.y, 1.0);
+                if entity == entities[7] {
+                    assert!(archetype.get_comp::<Position>(index).unwrap().is_some());
+                    assert!(archetype.get_comp_mut::<Position>(index).unwrap().is_none());
+                }
+            })
+            .unwrap();
+
+        // A per entity borrow blocks the column lock
+        let borrowed = archetype.get_comp_mut::<Health>(index).unwrap().unwrap();
+        assert_eq!(
+            world.query::<Health>().columns(|_, _| {}),
+            Err(WorldError::ComponentBorrowed)
+        );
+        assert!(world.query::<Position>().columns(|_, _| {}).is_ok());
+        drop(borrowed);
+        // Listing a component twice would alias it
+        assert_eq!(
+            world.query::<(Position, Position)>().columns_mut(|_, _| {}),
+            Err(WorldError::ComponentBorrowed)
+        );
+        #[cfg(feature = "rayon")]
+        assert_eq!(
+            world
+                .query::<Health>()
+                .par_columns_mut(|_, health| health.food -= 1.0),
+            Ok(2000)
+        );
+        let food = if cfg!(feature = "rayon") { 98.0 } else { 99.0 };
+        assert_eq!(
+            archetype
+                .get_comp::<Health>(index)
+                .unwrap()
+                .unwrap()
+                .as_ref()
+                .food,
+            food
+        );
+        // Removal fails while a column is locked
+        world
+            .query::<Position>()
+            .columns(|_, _| assert!(archetype.remove(index).is_err()))
+            .unwrap();
+    }
+
+    #[test]
+    pub fn component_borrow_state() {
+        let mut world = World::new(16);
+        world.add_archetype::<Player>(2);
+        let entities = world.spawn_batch((0..2).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+
+        // Borrows are tracked per component of each entity, so neighbours in the shared array do not interfere
+        let position = archetype.get_comp_mut::<Position>(0).unwrap().unwrap();
+        assert!(archetype.get_comp_mut::<Position>(0).unwrap().is_none());
+        assert!(archetype.get_comp_mut::<Health>(0).unwrap().is_some());
+        assert!(archetype.get_comp_mut::<Position>(1).unwrap().is_some());
+        assert!(archetype.remove(0).is_err());
+        drop(position);
+
+        let health = archetype.get_comp::<Health>(1).unwrap().unwrap();
+        let cloned = health.clone();
+        drop(health);
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_none());
+        drop(cloned);
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_some());
+        drop(archetype);
+
+        // Growing the archetype keeps every entity unborrowed
+        world.spawn_batch((2..100).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap();
+        assert!(archetype.capacity() >= 100);
+        for index in 0..100 {
+            assert!(archetype.get_comp_mut::<Position>(index).unwrap().is_some());
+        }
+        let location = world.get_entities().get_location(entities[1].id).unwrap();
+        assert_eq!(
+            archetype
+                .get_comp::<Position>(location.index)
+                .unwrap()
+                .unwrap()
+                .as_ref()
+                .x,
+            1.0
+        );
+    }
+
+    #[test]
+    pub fn borrow_policy() {
+        use crate::archetypes::borrow::{BorrowPolicy, MAX_READERS};
+
+        let mut world = World::new(16);
+        world.spawn_batch((0..2).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+
+        // Refused reads leave the state alone, so they can not wrap around the writer
+        let position = archetype.get_comp_mut::<Position>(0).unwrap().unwrap();
+        for _ in 0..300 {
+            assert!(archetype.get_comp::<Position>(0).unwrap().is_none());
+        }
+        assert!(archetype.get_comp_mut::<Position>(0).unwrap().is_none());
+        drop(position);
+
+        let readers = (0..MAX_READERS)
+            .map(|_| archetype.get_comp::<Position>(0).unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert!(archetype.get_comp::<Position>(0).unwrap().is_none());
+        assert!(archetype.get_comp_mut::<Position>(0).unwrap().is_none());
+        drop(readers);
+        assert!(archetype.get_comp_mut::<Position>(0).unwrap().is_some());
+
+        // Readers keep coming in while a writer waits
+        let reader = archetype.get_comp::<Health>(1).unwrap().unwrap();
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_none());
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_some());
+        drop(reader);
+
+        // A refused writer keeps new readers out until it gets the component
+        world.set_borrow_policy(BorrowPolicy::WriterPreferring);
+        assert_eq!(archetype.borrow_policy(), BorrowPolicy::WriterPreferring);
+        let reader = archetype.get_comp::<Health>(1).unwrap().unwrap();
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_none());
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_none());
+        assert!(archetype.get_comp::<Health>(0).unwrap().is_some());
+        drop(reader.clone());
+        drop(reader);
+        let writer = archetype.get_comp_mut::<Health>(1).unwrap().unwrap();
+        drop(writer);
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_some());
+
+        // A writer that gives up after being refused does not keep readers out once they drained
+        let reader = archetype.get_comp::<Health>(1).unwrap().unwrap();
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_none());
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_none());
+        drop(reader);
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_some());
+
+        // New archetypes follow the policy of the World
+        world.add_archetype::<Player>(4);
+        assert_eq!(
+            world.get_archetype::<Player>().unwrap().borrow_policy(),
+            BorrowPolicy::WriterPreferring
+        );
+    }
+
+    #[test]
+    pub fn lock_entity() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        let mut world = World::new(16);
+        world
+            .spawn_batch((0..2).map(|i| Player {
+                health: Health {
+                    health: 100.0,
+                    food: 10.0 * i as f32,
+                },
+                ..player(i)
+            }))
+            .unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+
+        // Trade food between two players while nothing else can see either of them half way
+        {
+            let mut seller = archetype.lock_entity(1).unwrap().unwrap();
+            let mut buyer = archetype.lock_entity(0).unwrap().unwrap();
+            assert!(archetype.lock_entity(0).is_err());
+            assert!(archetype.get_comp::<Health>(0).is_err());
+            assert!(archetype.remove(1).is_err());
+            assert!(seller.view::<(Position, Position)>().is_none());
+            let (position, health) = seller.view::<(Position, Health)>().unwrap();
+            health.food -= 5.0;
+            position.y = 1.0;
+            buyer.get_mut::<Health>().unwrap().food += 5.0;
+            assert_eq!(seller.get::<Health>().unwrap().food, 5.0);
+        }
+        assert_eq!(
+            archetype
+                .get_comp::<Health>(0)
+                .unwrap()
+                .unwrap()
+                .as_ref()
+                .food,
+            5.0
+        );
+
+        // A borrowed component keeps the entity from being locked
+        let borrowed = archetype.get_comp::<Position>(1).unwrap().unwrap();
+        assert!(archetype.lock_entity(1).is_err());
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_some());
+        // A failed removal leaves the entity as it was
+        assert!(archetype.remove(1).is_err());
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_some());
+
+        // The async variant keeps new borrows out while it waits
+        let mut context = Context::from_waker(Waker::noop());
+        let mut lock = Box::pin(archetype.lock_entity_async(1));
+        assert!(lock.as_mut().poll(&mut context).is_pending());
+        assert!(archetype.get_comp::<Health>(1).is_err());
+        // Only the waiting future clears its mark
+        assert!(archetype.lock_entity(1).is_err());
+        assert!(archetype.remove(1).is_err());
+        assert!(archetype.get_comp::<Health>(1).is_err());
+        drop(borrowed);
+        let Poll::Ready(Some(mut guard)) = lock.as_mut().poll(&mut context) else {
+            panic!("The entity should be locked once the borrow is gone");
+        };
+        assert_eq!(guard.get_mut::<Position>().unwrap().y, 1.0);
+        drop(guard);
+        assert!(archetype.get_comp_mut::<Position>(1).unwrap().is_some());
+
+        // Dropping a waiting future lets borrows in again
+        let borrowed = archetype.get_comp::<Position>(1).unwrap().unwrap();
+        let mut lock = Box::pin(archetype.lock_entity_async(1));
+        assert!(lock.as_mut().poll(&mut context).is_pending());
+        drop(lock);
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_some());
+        drop(borrowed);
+
+        // The entity was removed while waiting for another lock
+        let guard = archetype.lock_entity(1).unwrap().unwrap();
+        let mut lock = Box::pin(archetype.lock_entity_async(1));
+        assert!(lock.as_mut().poll(&mut context).is_pending());
+        drop(guard);
+        archetype.remove(1).unwrap();
+        assert!(matches!(
+            lock.as_mut().poll(&mut context),
+            Poll::Ready(None)
+        ));
+
+        // A removed slot can not be locked, so its dropped components are never handed out
+        assert!(matches!(archetype.lock_entity(1), Ok(None)));
+        let mut lock = Box::pin(archetype.lock_entity_async(1));
+        assert!(matches!(
+            lock.as_mut().poll(&mut context),
+            Poll::Ready(None)
+        ));
+        assert!(matches!(archetype.lock_entity(1), Ok(None)));
+    }
+
+    #[test]
+    pub fn transactions() {
+        use crate::transaction::TransactionError;
+
+        let mut world = World::new(16);
+        let entities = world
+            .spawn_batch((0..3).map(|i| Player {
+                health: Health {
+                    health: 100.0,
+                    food: 10.0,
+                },
+                ..player(i)
+            }))
+            .unwrap();
+        let (attacker, target) = (entities[2].clone(), entities[0].clone());
+        let health = |world: &World, entity: &Entity| {
+            let location = world.get_entities().get_location(entity.id).unwrap();
+            world
+                .get_archetype::<Player>()
+                .unwrap()
+                .get_comp::<Health>(location.index)
+                .unwrap()
+                .unwrap()
+                .as_ref()
+                .health
+        };
+
+        // Damage with lifesteal changes both entities or neither
+        let dealt = world
+            .transaction(&[attacker.clone(), target.clone()], |tx| {
+                assert_eq!(tx.entities().next(), Some(&target));
+                tx.get_mut::<Health>(&target).unwrap().health -= 30.0;
+                tx.get_mut::<Health>(&attacker).unwrap().health += 15.0;
+                Ok::<_, ()>(30.0)
+            })
+            .unwrap();
+        assert_eq!(dealt, 30.0);
+        assert_eq!(health(&world, &target), 70.0);
+        assert_eq!(health(&world, &attacker), 115.0);
+
+        let result = world.transaction(&[attacker.clone(), target.clone()], |tx| {
+            tx.get_mut::<Health>(&target).unwrap().health -= 80.0;
+            tx.get_mut::<Health>(&target).unwrap().health -= 1.0;
+            tx.get_mut::<Health>(&attacker).unwrap().health += 40.0;
+            if tx.get::<Health>(&target).unwrap().health < 0.0 {
+                return Err("target would die");
+            }
+            Ok(())
+        });
+        assert_eq!(result, Err(TransactionError::Aborted("target would die")));
+        assert_eq!(health(&world, &target), 70.0);
+        assert_eq!(health(&world, &attacker), 115.0);
+
+        // A borrowed entity fails the whole transaction without locking the others
+        let location = world.get_entities().get_location(target.id).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+        let borrowed = archetype.get_comp::<Position>(location.index).unwrap();
+        let result = world.transaction(&[attacker.clone(), target.clone()], |_| Ok::<_, ()>(()));
+        assert_eq!(result, Err(TransactionError::Conflict));
+        drop(borrowed);
+        assert!(archetype.lock_entity(2).unwrap().is_some());
+
+        world.remove_entity(entities[1].clone()).unwrap();
+        let result = world.transaction(&[entities[1].clone()], |_| Ok::<_, ()>(()));
+        assert_eq!(result, Err(TransactionError::EntityNotFound));
+    }
+
+    #[cfg(feature = "debug-borrows")]
+    #[test]
+    pub fn active_borrows() {
+        let mut world = World::new(16);
+        world.spawn_batch((0..4).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap().clone();
+        let shared = archetype.get_comp::<Position>(1).unwrap().unwrap();
+        let cloned = crate::diagnostics::with_borrow_context(7, || shared.clone());
+        drop(shared);
+        let line = line!() + 1;
+        mem::forget(archetype.get_comp_mut::<Health>(3).unwrap().unwrap());
+        assert!(archetype
+            .get_comp::<(Position, Health)>(2)
+            .unwrap()
+            .is_some());
+        world.increment_change_tick();
+
+        let borrows = world.active_borrows();
+        assert_eq!(borrows.len(), 2);
+        let leaked = borrows.iter().find(|b| b.mutable).unwrap();
+        assert_eq!(leaked.entity_index, 3);
+        assert_eq!(leaked.entity_id, 3);
+        assert!(leaked.component.ends_with("Health"));
+        assert_eq!(leaked.archetype, Player::archetype_id());
+        // The borrow points at the code that took it, not at the library
+        assert_eq!(leaked.location.file(), file!());
+        assert_eq!(leaked.location.line(), line);
+        assert_eq!(leaked.context, None);
+        let shared = borrows.iter().find(|b| !b.mutable).unwrap();
+        assert_eq!(shared.entity_index, 1);
+        assert_eq!(shared.context, Some(7));
+        assert_eq!(crate::diagnostics::borrow_context(), None);
+
+        drop(cloned);
+        assert_eq!(world.active_borrows().len(), 1);
+
+        // The leak is why the archetype can not be resized
+        let taken = world.take_archetype::<Player>().unwrap();
+        drop(archetype);
+        let archetype = taken.resize(None).unwrap_err();
+        world.push_archetype::<Player>(archetype);
+        assert_eq!(world.active_borrows().len(), 1);
+
+        // Only borrows older than the limit are reported
+        assert!(world.long_borrows(2).is_empty());
+        world.increment_change_tick();
+        world.increment_change_tick();
+        let long = world.long_borrows(2);
+        assert_eq!(long.len(), 1);
+        assert_eq!(long[0].entity_index, 3);
+    }
+
+    #[test]
+    pub fn owned_component_refs() {
+        use crate::component_ref::{OwnedComponentRef, OwnedMutComponentRef};
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        fn assert_send<T: Send + 'static>(_: &T) {}
+
+        let mut world = World::new(16);
+        world.spawn_batch((0..2).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap();
+        let mut health: OwnedMutComponentRef<Health> =
+            archetype.get_comp_mut_owned(1).unwrap().unwrap();
+        let position: OwnedComponentRef<Position> = archetype.get_comp_owned(1).unwrap().unwrap();
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_none());
+        assert!(archetype.get_comp_mut::<Position>(1).unwrap().is_none());
+
+        // Held across an await point and moved to another thread
+        let mut task = Box::pin(async move {
+            std::future::ready(()).await;
+            health.as_mut().food -= position.as_ref().x;
+            let position = std::thread::spawn(move || position.clone()).join().unwrap();
+            (health, position)
+        });
+        assert_send(&task);
+        let Poll::Ready((health, position)) =
+            task.as_mut().poll(&mut Context::from_waker(Waker::noop()))
+        else {
+            panic!("The task does not wait on anything");
+        };
+
+        // The refs keep the archetype alive after it left the World, but stop it from being resized
+        let taken = world.take_archetype::<Player>().unwrap();
+        drop(world);
+        let taken = taken.resize(None).unwrap_err();
+        assert_eq!(health.as_ref().food, 99.0);
+        drop(taken);
+        assert_eq!(position.as_ref().x, 1.0);
+        drop(health);
+        drop(position);
+    }
+
+    #[test]
+    pub fn component_ref_projections() {
+        use crate::component_ref::{ComponentRef, MutComponentRef};
+
+        let mut world = World::new(16);
+        world.spawn_batch((0..2).map(player)).unwrap();
+        let archetype = world.get_archetype::<Player>().unwrap();
+
+        let mut health = archetype.get_comp_mut::<Health>(1).unwrap().unwrap();
+        health.food -= 10.0;
+        assert_eq!(health.food, 90.0);
+
+        // A projection keeps the whole component locked
+        let mut food = MutComponentRef::map(health, |health| &mut health.food);
+        *food += 1.0;
+        assert!(archetype.get_comp::<Health>(1).unwrap().is_none());
+
+        // Downgrading lets readers in but keeps writers out the whole time
+        let food = MutComponentRef::downgrade(food);
+        assert_eq!(*food, 91.0);
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_none());
+        let health = archetype.get_comp::<Health>(1).unwrap().unwrap();
+        assert_eq!(health.food, 91.0);
+        drop(health);
+        let food = ComponentRef::map(food, |food| food);
+        let copy = food.clone();
+        drop(food);
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_none());
+        drop(copy);
+
+        let position = archetype.get_comp::<Position>(0).unwrap().unwrap();
+        let x = ComponentRef::map(position, |position| &position.x);
+        assert_eq!(*x, 0.0);
+        assert!(archetype.get_comp_mut::<Position>(0).unwrap().is_none());
+        drop(x);
+        assert!(archetype.get_comp_mut::<Position>(0).unwrap().is_some());
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_some());
+
+        // A panicking projection still releases the borrow
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let health = archetype.get_comp_mut::<Health>(1).unwrap().unwrap();
+            MutComponentRef::map(health, |_| -> &mut f32 { panic!("projection failed") });
+        }));
+        assert!(panicked.is_err());
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let health = archetype.get_comp::<Health>(1).unwrap().unwrap();
+            ComponentRef::map(health, |_| -> &f32 { panic!("projection failed") });
+        }));
+        assert!(panicked.is_err());
+        assert!(archetype.get_comp_mut::<Health>(1).unwrap().is_some());
+    }
+
+    #[test]
+    pub fn entity_refs() {
+        #[derive(Debug, Component)]
+        struct Name;
+
+        let mut world = World::new(16);
+        let entities = world.spawn_batch((0..2).map(player)).unwrap();
+
+        let player = world.entity(&entities[1]).unwrap();
+        assert!(player.has::<(Position, Health)>());
+        assert!(!player.has::<(Position, Name)>());
+        let mut names = player
+            .components()
+            .iter()
+            .map(|info| info.name())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                std::any::type_name::<Health>(),
+                std::any::type_name::<Position>()
+            ]
+        );
+        assert_eq!(player.get::<Position>().unwrap().unwrap().x, 1.0);
+        assert!(player.get::<Name>().unwrap().is_none());
+
+        let mut health = player.get_mut::<Health>().unwrap().unwrap();
+        health.food -= 10.0;
+        assert_eq!(
+            player.get::<Health>().unwrap_err(),
+            crate::world::WorldError::ComponentBorrowed
+        );
+        drop(health);
+        assert_eq!(player.get::<Health>().unwrap().unwrap().food, 90.0);
+        // Listing a Component twice is not mistaken for a borrowed one
+        assert_eq!(
+            player.get_mut::<(Health, Health)>().unwrap_err(),
+            crate::world::WorldError::DuplicateComponent
+        );
+        assert!(player.get_mut::<Health>().unwrap().is_some());
+
+        let reserved = world.reserve_entity();
+        let reserved = world.entity(&reserved).unwrap();
+        assert!(reserved.location().is_none());
+        assert!(reserved.components().is_empty());
+        assert!(reserved.get::<Position>().unwrap().is_none());
+
+        let borrowed = world.get_archetype::<Player>().unwrap().clone();
+        let position = borrowed.get_comp::<Position>(0).unwrap().unwrap();
+        let player = world.entity_mut(&entities[0]).unwrap();
+        assert_eq!(
+            player.despawn().unwrap_err(),
+            crate::world::WorldError::ComponentBorrowed
+        );
+        drop(position);
+        assert!(world.entity(&entities[0]).is_some());
+        world.entity_mut(&entities[0]).unwrap().despawn().unwrap();
+        assert!(world.entity(&entities[0]).is_none());
+        assert!(world.entity_mut(&entities[0]).is_none());
+        assert_eq!(world.entity(&entities[1]).unwrap().components().len(), 2);
+    }
+
+    #[test]
+    pub fn get_by_entity() {
+        use crate::world::WorldError;
+        use dumbledore_macro::Bundle;
+
+        #[derive(Bundle)]
+        struct Marker {
+            position: Position,
+        }
+
+        let mut world = World::new(16);
+        let entities = world.spawn_batch((0..3).map(player)).unwrap();
+        let position = world
+            .spawn_batch([Marker {
+                position: Position { x: 9.0, y: 9.0 },
+            }])
+            .unwrap()[0]
+            .clone();
+
+        assert_eq!(world.get::<Position>(&entities[2]).unwrap().unwrap().x, 2.0);
+        assert_eq!(world.get::<Position>(&position).unwrap().unwrap().x, 9.0);
+        assert!(world.get::<Health>(&position).unwrap().is_none());
+
+        let (mut pos, health) = world
+            .get_mut::<(Position, Health)>(&entities[1])
+            .unwrap()
+            .unwrap();
+        pos.y = health.food;
+        assert_eq!(
+            world.get::<Position>(&entities[1]).unwrap_err(),
+            WorldError::ComponentBorrowed
+        );
+        drop((pos, health));
+        let (pos, health) = world
+            .get::<(Position, Health)>(&entities[1])
+            .unwrap()
+            .unwrap();
+        assert_eq!((pos.x, pos.y, health.health), (1.0, 100.0, 100.0));
+        drop((pos, health));
+
+        world.despawn_batch(&entities[..1]).unwrap();
+        assert_eq!(
+            world.get::<Position>(&entities[0]).unwrap_err(),
+            WorldError::EntityNotFound
+        );
+        let reused = world.reserve_entity();
+        assert_eq!(
+            world.get::<Position>(&entities[0]).unwrap_err(),
+            WorldError::EntityNotFound
+        );
+        assert!(world.get_mut::<Position>(&reused).unwrap().is_none());
+    }
 }
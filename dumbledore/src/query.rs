@@ -0,0 +1,257 @@
+//! Iterating every entity that has a set of components.
+//!
+//! ```no_run, rust, ignore
+//! world.query::<(Position, Velocity)>().for_each(|entity, (mut position, velocity)| {
+//!     position.as_mut().x += velocity.as_ref().x;
+//! });
+//! ```
+//! The `columns` methods lock each component column once for the whole query and hand out plain references,
+//! which avoids the per entity lock traffic for large archetypes.
+use crate::archetypes::arche::{Archetype, ColumnGuard};
+use crate::component::ComponentLookup;
+use crate::entities::entity::Entity;
+use crate::world::{World, WorldError};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+
+/// The number of entities handed to one task by [Query::par_for_each]
+pub const DEFAULT_CHUNK_SIZE: u32 = 1024;
+
+/// The Archetypes that contain every component in `Q`.
+///
+/// Created with [World::query]. Components are borrowed one entity at a time with the same locks as
+/// [Archetype::get_comp_mut], so entities borrowed elsewhere are skipped instead of aliased.
+pub struct Query<'w, Q> {
+    world: &'w World,
+    archetypes: Vec<(u32, &'w Archetype)>,
+    chunk_size: u32,
+    _lookup: PhantomData<fn() -> Q>,
+}
+
+impl World {
+    pub fn query<'w, Q: ComponentLookup<'w>>(&'w self) -> Query<'w, Q> {
+        let types = Q::type_ids();
+        let archetypes = self
+            .archetypes
+            .iter()
+            .filter(|(_, archetype)| {
+                types
+                    .iter()
+                    .all(|typ| archetype.0.component_offsets.get(typ).is_some())
+            })
+            .map(|(id, archetype)| (*id, archetype))
+            .collect();
+        Query {
+            world: self,
+            archetypes,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            _lookup: PhantomData,
+        }
+    }
+}
+
+impl<'w, Q: ComponentLookup<'w>> Query<'w, Q> {
+    /// Sets how many entities [Query::par_for_each] hands to a task at once.
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+    /// Calls `f` with every matching Entity and its components.
+    ///
+    /// # Returns
+    /// The number of entities visited. Entities with a component borrowed elsewhere are skipped.
+    pub fn for_each<F: FnMut(Entity, Q::MutResponse)>(&self, mut f: F) -> usize {
+        self.archetypes
+            .iter()
+            .map(|(id, archetype)| self.run(*id, archetype, 0..archetype_len(archetype), &mut f))
+            .sum()
+    }
+    /// Splits the matching Archetypes into chunks of entities and runs them on the rayon thread pool.
+    ///
+    /// # Returns
+    /// The number of entities visited. Entities with a component borrowed elsewhere are skipped.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F: Fn(Entity, Q::MutResponse) + Send + Sync>(&self, f: F) -> usize {
+        use rayon::prelude::*;
+
+        self.chunks()
+            .into_par_iter()
+            .map(|(id, archetype, range)| self.run(id, archetype, range, &mut |e, c| f(e, c)))
+            .sum()
+    }
+
+    #[cfg(feature = "rayon")]
+    fn chunks(&self) -> Vec<(u32, &'w Archetype, Range<u32>)> {
+        self.archetypes
+            .iter()
+            .flat_map(|(id, archetype)| {
+                let len = archetype_len(archetype);
+                (0..len)
+                    .step_by(self.chunk_size as usize)
+                    .map(move |start| (*id, *archetype, start..len.min(start + self.chunk_size)))
+            })
+            .collect()
+    }
+
+    fn run(
+        &self,
+        archetype_id: u32,
+        archetype: &'w Archetype,
+        range: Range<u32>,
+        f: &mut impl FnMut(Entity, Q::MutResponse),
+    ) -> usize {
+        let mut visited = 0;
+        for index in range {
+            let Some(entity) = self.entity_at(archetype_id, archetype, index) else {
+                continue;
+            };
+            // None if one of the components is borrowed
+            if let Ok(Some(components)) = archetype.get_comp_mut::<Q>(index) {
+                f(entity, components);
+                visited += 1;
+            }
+        }
+        visited
+    }
+
+    /// The Entity in the slot, or None if the slot is free.
+    fn entity_at(&self, archetype_id: u32, archetype: &Archetype, index: u32) -> Option<Entity> {
+        let id = archetype.0.entity_data[index as usize]
+            .entity_id
+            .load(Ordering::Relaxed);
+        let (entity, location) = self.world.entities.get_entity(id)?;
+        let location = location?;
+        (location.archetype == archetype_id && location.index == index).then_some(entity)
+    }
+}
+
+/// The references handed out by the column methods can not outlive the call, since the column locks are released
+/// when it returns.
+impl<'w, Q> Query<'w, Q>
+where
+    Q: for<'a> ComponentLookup<'a>,
+{
+    /// Locks every column of `Q` for writing and calls `f` with plain mutable references.
+    ///
+    /// Marks the components as changed.
+    ///
+    /// # Returns
+    /// The number of entities visited. Err if a column or one of its entities is borrowed elsewhere, nothing is visited then.
+    pub fn columns_mut<F>(&self, mut f: F) -> Result<usize, WorldError>
+    where
+        F: for<'a> FnMut(Entity, <Q as ComponentLookup<'a>>::PlainMut),
+    {
+        let _guards = self.lock_columns(true)?;
+        Ok(self
+            .archetypes
+            .iter()
+            .map(|(id, archetype)| {
+                self.run_columns_mut(*id, archetype, 0..archetype_len(archetype), &mut f)
+            })
+            .sum())
+    }
+    /// Locks every column of `Q` for reading and calls `f` with plain shared references.
+    ///
+    /// # Returns
+    /// The number of entities visited. Err if a column or one of its entities is mutably borrowed elsewhere.
+    pub fn columns<F>(&self, mut f: F) -> Result<usize, WorldError>
+    where
+        F: for<'a> FnMut(Entity, <Q as ComponentLookup<'a>>::PlainRef),
+    {
+        let _guards = self.lock_columns(false)?;
+        let mut visited = 0;
+        for (id, archetype) in self.archetypes.iter() {
+            for index in 0..archetype_len(archetype) {
+                let Some(entity) = self.entity_at(*id, archetype, index) else {
+                    continue;
+                };
+                let ptr = row_ptr(archetype, index);
+                let components = unsafe {
+                    Q::plain_ref(|typ| {
+                        let (offset, _) = archetype.0.component_offsets.get(typ)?;
+                        Some(ptr.add(*offset))
+                    })
+                };
+                if let Some(components) = components {
+                    f(entity, components);
+                    visited += 1;
+                }
+            }
+        }
+        Ok(visited)
+    }
+    /// [Query::columns_mut] split into chunks on the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn par_columns_mut<F>(&self, f: F) -> Result<usize, WorldError>
+    where
+        F: for<'a> Fn(Entity, <Q as ComponentLookup<'a>>::PlainMut) + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        let _guards = self.lock_columns(true)?;
+        Ok(self
+            .chunks()
+            .into_par_iter()
+            .map(|(id, archetype, range)| {
+                self.run_columns_mut(id, archetype, range, &mut |e, c| f(e, c))
+            })
+            .sum())
+    }
+
+    /// Locks the columns of `Q` in every matching Archetype, or none of them.
+    fn lock_columns(&self, write: bool) -> Result<Vec<ColumnGuard<'w>>, WorldError> {
+        let mut guards = Vec::new();
+        for (_, archetype) in self.archetypes.iter() {
+            for typ in Q::type_ids() {
+                // Listing a component twice fails here instead of aliasing it
+                let guard = archetype
+                    .lock_column(&typ, write)
+                    .ok_or(WorldError::ComponentBorrowed)?;
+                guards.push(guard);
+            }
+        }
+        Ok(guards)
+    }
+
+    fn run_columns_mut(
+        &self,
+        archetype_id: u32,
+        archetype: &Archetype,
+        range: Range<u32>,
+        f: &mut impl for<'a> FnMut(Entity, <Q as ComponentLookup<'a>>::PlainMut),
+    ) -> usize {
+        let tick = archetype.0.change_tick.load(Ordering::Relaxed);
+        let mut visited = 0;
+        for index in range {
+            let Some(entity) = self.entity_at(archetype_id, archetype, index) else {
+                continue;
+            };
+            let data = &archetype.0.entity_data[index as usize];
+            let ptr = data.inner_ptrs.load(Ordering::Relaxed);
+            // The column locks make these the only references to the components
+            let components = unsafe {
+                Q::plain_mut(|typ| {
+                    let (offset, component) = archetype.0.component_offsets.get(typ)?;
+                    data.changed_ticks[*component as usize].store(tick, Ordering::Relaxed);
+                    Some(ptr.add(*offset))
+                })
+            };
+            if let Some(components) = components {
+                f(entity, components);
+                visited += 1;
+            }
+        }
+        visited
+    }
+}
+
+fn archetype_len(archetype: &Archetype) -> u32 {
+    archetype.0.entities_len.load(Ordering::Relaxed)
+}
+
+fn row_ptr(archetype: &Archetype, index: u32) -> *mut u8 {
+    archetype.0.entity_data[index as usize]
+        .inner_ptrs
+        .load(Ordering::Relaxed)
+}
@@ -0,0 +1,234 @@
+use crate::archetypes::ComponentInfo;
+use crate::entities::entity::Entity;
+use crate::entities::entity_map::EntityMap;
+use crate::replication::packet::DeltaPacket;
+use crate::replication::{ReplicationError, ReplicationRegistry};
+use crate::world::World;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::ptr;
+
+/// Mirrors the Entities of a server World into a local World.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaApplier {
+    /// Server Entities to local Entities
+    map: EntityMap,
+    /// Server Archetype ids to local Archetype ids
+    archetypes: HashMap<u32, u32>,
+}
+
+/// The components of one Entity, decoded and ready to be moved into an Archetype.
+type Decoded = Vec<(ComponentInfo, Box<dyn Any + Send + Sync>)>;
+
+impl ReplicaApplier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The map from server Entities to local Entities.
+    pub fn entity_map(&self) -> &EntityMap {
+        &self.map
+    }
+    /// Returns the local Entity mirroring the server Entity.
+    pub fn local(&self, server: &Entity) -> Option<&Entity> {
+        self.map.get(server)
+    }
+    /// Returns the id of the local Archetype mirroring the server Archetype.
+    pub fn local_archetype(&self, server: u32) -> Option<u32> {
+        self.archetypes.get(&server).copied()
+    }
+    /// Applies the packet to the local World.
+    ///
+    /// Spawned entities are put in a local Archetype made of the replicated components.
+    /// That is an existing Archetype with exactly those components if there is one,
+    /// otherwise a new one with the server id or, if that id is taken, an unused id.
+    ///
+    /// The whole packet is decoded and checked before the World is changed,
+    /// so a malformed packet or one with an unknown component leaves it as it was.
+    /// If a despawned Entity is borrowed, [ReplicationError::ComponentLocked] is returned before anything is changed.
+    /// If a changed component is borrowed the same error is returned after the spawns and despawns were applied.
+    pub fn apply(
+        &mut self,
+        world: &mut World,
+        registry: &ReplicationRegistry,
+        packet: &DeltaPacket,
+    ) -> Result<(), ReplicationError> {
+        let mut archetypes: BTreeMap<u32, (Vec<ComponentInfo>, usize)> = BTreeMap::new();
+        for update in packet.spawned.iter() {
+            let mut infos = Vec::with_capacity(update.components.len());
+            for (id, _) in update.components.iter() {
+                infos.push(registry.codec(*id)?.0.clone());
+            }
+            infos.sort_unstable_by_key(|c| c.id);
+            let (expected, count) = archetypes
+                .entry(update.archetype)
+                .or_insert_with(|| (infos.clone(), 0));
+            if *expected != infos {
+                return Err(ReplicationError::Malformed);
+            }
+            *count += 1;
+        }
+        // Server Archetypes with the same replicated components share a local Archetype
+        let mut local_archetypes = HashMap::with_capacity(archetypes.len());
+        let mut reserve: BTreeMap<u32, (Vec<ComponentInfo>, usize)> = BTreeMap::new();
+        for (server, (infos, count)) in archetypes {
+            let local = self.resolve_archetype(world, server, &infos, &local_archetypes);
+            local_archetypes.insert(server, local);
+            reserve.entry(local).or_insert_with(|| (infos, 0)).1 += count;
+        }
+        for update in packet.changed.iter() {
+            let Some(local) = self.map.get(&update.entity) else {
+                continue;
+            };
+            let archetype = world
+                .entities
+                .get_location(local.id)
+                .and_then(|location| world.archetypes.get(&location.archetype))
+                .ok_or(ReplicationError::Malformed)?;
+            for (id, _) in update.components.iter() {
+                let info = &registry.codec(*id)?.0;
+                if archetype.0.component_offsets.get(&info.id).is_none() {
+                    return Err(ReplicationError::Malformed);
+                }
+            }
+        }
+
+        // Components can reference Entities spawned by the same packet, so those are allocated before decoding.
+        // They are freed again if the packet is rejected.
+        world.ensure_entity_capacity(packet.spawned.len())?;
+        let mut replaced = Vec::with_capacity(packet.spawned.len());
+        for update in packet.spawned.iter() {
+            let local = world.entities.alloc();
+            replaced.push(self.map.insert(update.entity.clone(), local));
+        }
+        let decoded = decode_all(registry, packet, &self.map);
+        let prepared = decoded.and_then(|decoded| {
+            for (local, (infos, count)) in reserve {
+                world.ensure_archetype(local, &infos, count)?;
+            }
+            Ok(decoded)
+        });
+        let mut stale = Vec::with_capacity(packet.despawned.len() + packet.spawned.len());
+        stale.extend(
+            packet
+                .despawned
+                .iter()
+                .filter_map(|server| self.map.get(server).cloned()),
+        );
+        stale.extend(replaced.iter().flatten().cloned());
+        let prepared = prepared.and_then(|decoded| {
+            world
+                .despawn_batch(&stale)
+                .map_err(|_| ReplicationError::ComponentLocked)?;
+            Ok(decoded)
+        });
+        let (spawned, changed) = match prepared {
+            Ok(decoded) => decoded,
+            Err(error) => {
+                for (update, previous) in packet.spawned.iter().zip(replaced).rev() {
+                    let local = match previous {
+                        Some(previous) => self.map.insert(update.entity.clone(), previous),
+                        None => self.map.remove(&update.entity),
+                    };
+                    if let Some(local) = local {
+                        world.entities.free(local.id);
+                    }
+                }
+                return Err(error);
+            }
+        };
+
+        for server in packet.despawned.iter() {
+            self.map.remove(server);
+        }
+        self.archetypes.extend(local_archetypes.iter());
+        for (update, components) in packet.spawned.iter().zip(spawned) {
+            let local = self.map.map(&update.entity);
+            world.add_entity_boxed(&local, local_archetypes[&update.archetype], components);
+        }
+        for (update, components) in packet.changed.iter().zip(changed) {
+            let Some(local) = self.map.get(&update.entity) else {
+                continue;
+            };
+            // Despawning can move rows, so the location is looked up again
+            let Some(location) = world.entities.get_location(local.id) else {
+                continue;
+            };
+            let archetype = world
+                .archetypes
+                .get(&location.archetype)
+                .ok_or(ReplicationError::Malformed)?;
+            for (info, value) in components {
+                let raw = archetype
+                    .get_raw_mut(location.index, &info.id)
+                    .map_err(|_| ReplicationError::ComponentLocked)?
+                    .ok_or(ReplicationError::Malformed)?;
+                let data = Box::into_raw(value) as *mut u8;
+                unsafe {
+                    (info.drop)(raw.component);
+                    ptr::copy_nonoverlapping(data, raw.component, info.layout.size());
+                    if info.layout.size() != 0 {
+                        std::alloc::dealloc(data, info.layout);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Picks the local Archetype for a server Archetype with the replicated components `infos`.
+    fn resolve_archetype(
+        &self,
+        world: &World,
+        server: u32,
+        infos: &[ComponentInfo],
+        taken: &HashMap<u32, u32>,
+    ) -> u32 {
+        let matches = |id: &u32| match world.archetypes.get(id) {
+            Some(archetype) => archetype.0.components.as_ref() == infos,
+            None => !taken.values().any(|taken| taken == id),
+        };
+        if let Some(local) = self.archetypes.get(&server).filter(|id| matches(id)) {
+            return *local;
+        }
+        if let Some((local, _)) = world
+            .archetypes
+            .iter()
+            .find(|(_, archetype)| archetype.0.components.as_ref() == infos)
+        {
+            return *local;
+        }
+        if !world.archetypes.contains_key(&server) && matches(&server) {
+            return server;
+        }
+        (0..=u32::MAX)
+            .rev()
+            .find(|id| !world.archetypes.contains_key(id) && matches(id))
+            .expect("Every Archetype id is in use")
+    }
+}
+
+/// Decodes the components of the spawned and the changed Entities, in the order of the packet.
+fn decode_all(
+    registry: &ReplicationRegistry,
+    packet: &DeltaPacket,
+    map: &EntityMap,
+) -> Result<(Vec<Decoded>, Vec<Decoded>), ReplicationError> {
+    let decode = |components: &[(u16, Vec<u8>)]| -> Result<Decoded, ReplicationError> {
+        let mut decoded = Vec::with_capacity(components.len());
+        for (id, bytes) in components.iter() {
+            let (info, codec) = registry.codec(*id)?;
+            decoded.push((info.clone(), codec.decode(bytes, map)?));
+        }
+        Ok(decoded)
+    };
+    let spawned = packet
+        .spawned
+        .iter()
+        .map(|update| decode(&update.components))
+        .collect::<Result<_, _>>()?;
+    let changed = packet
+        .changed
+        .iter()
+        .map(|update| decode(&update.components))
+        .collect::<Result<_, _>>()?;
+    Ok((spawned, changed))
+}
@@ -0,0 +1,104 @@
+use crate::entities::entity::Entity;
+use crate::spatial::{Spatial, SpatialIndex};
+use crate::world::World;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InterestEvent {
+    /// The Entity came within the radius of the observer.
+    Enter { observer: Entity, entity: Entity },
+    /// The Entity left the radius of the observer or no longer exists.
+    Leave { observer: Entity, entity: Entity },
+}
+
+#[derive(Debug, Clone)]
+struct Observer {
+    radius: f32,
+    visible: HashSet<Entity>,
+}
+
+/// Tracks which Entities are near each observer.
+///
+/// The interest set of an observer is every Entity with the Component `P` within its radius, not including itself.
+/// It can be passed to [ClientReplicator::encode_delta](crate::replication::server::ClientReplicator::encode_delta).
+#[derive(Debug, Clone)]
+pub struct InterestManager<P: Spatial> {
+    index: SpatialIndex<P>,
+    observers: HashMap<Entity, Observer>,
+}
+
+impl<P: Spatial> InterestManager<P> {
+    /// # Arguments
+    /// * `cell_size` - The cell size of the spatial index. Around the most common radius works well.
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            index: SpatialIndex::new(cell_size),
+            observers: HashMap::new(),
+        }
+    }
+    pub fn index(&self) -> &SpatialIndex<P> {
+        &self.index
+    }
+    /// Starts tracking the Entities within `radius` of the observer.
+    ///
+    /// The Entities it can see will be reported as [InterestEvent::Enter] on the next update.
+    pub fn add_observer(&mut self, observer: Entity, radius: f32) {
+        self.observers
+            .entry(observer)
+            .or_insert_with(|| Observer {
+                radius,
+                visible: HashSet::new(),
+            })
+            .radius = radius;
+    }
+    /// Stops tracking the observer. No events are emitted.
+    pub fn remove_observer(&mut self, observer: &Entity) -> bool {
+        self.observers.remove(observer).is_some()
+    }
+    pub fn observers(&self) -> impl Iterator<Item = &Entity> {
+        self.observers.keys()
+    }
+    /// The Entities the observer could see as of the last update.
+    pub fn interest(&self, observer: &Entity) -> impl Iterator<Item = &Entity> {
+        self.observers
+            .get(observer)
+            .into_iter()
+            .flat_map(|observer| observer.visible.iter())
+    }
+    /// Syncs the spatial index and recomputes the interest set of every observer.
+    ///
+    /// Entities that were removed from the World leave every interest set.
+    /// Observers that were removed from the World are dropped after leaving everything they could see.
+    pub fn update(&mut self, world: &World) -> Vec<InterestEvent> {
+        self.index.sync(world);
+        let mut events = Vec::new();
+        self.observers.retain(|observer, state| {
+            let alive = world.entities.is_alive(observer);
+            let mut visible = HashSet::new();
+            if let Some(center) = self.index.position(observer).filter(|_| alive) {
+                self.index
+                    .grid()
+                    .for_each_in_radius(center, state.radius, |entity, _| {
+                        if entity != observer {
+                            visible.insert(entity.clone());
+                        }
+                    });
+            }
+            for entity in state.visible.difference(&visible) {
+                events.push(InterestEvent::Leave {
+                    observer: observer.clone(),
+                    entity: entity.clone(),
+                });
+            }
+            for entity in visible.difference(&state.visible) {
+                events.push(InterestEvent::Enter {
+                    observer: observer.clone(),
+                    entity: entity.clone(),
+                });
+            }
+            state.visible = visible;
+            alive
+        });
+        events
+    }
+}
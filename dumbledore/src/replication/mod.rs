@@ -0,0 +1,171 @@
+//! Replicates Entities from a server World into client Worlds.
+//!
+//! The server keeps a [server::ClientReplicator] per client and produces a [packet::DeltaPacket] each tick.
+//! The client feeds the packets into a [client::ReplicaApplier] which mirrors the entities into its own World.
+//!
+//! Components are only replicated if they have a [ComponentCodec] in the [ReplicationRegistry].
+//! Both sides must register the codecs in the same order.
+use crate::archetypes::ComponentInfo;
+use crate::component::Component;
+use crate::entities::entity_map::EntityMap;
+use crate::snapshot::binary::Pod;
+use crate::world::WorldError;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+
+pub mod client;
+pub mod interest;
+pub mod packet;
+pub mod server;
+
+/// Converts a Component to and from the bytes sent over the network.
+pub trait ComponentCodec: Send + Sync + 'static {
+    type Component: Component;
+
+    fn encode(&self, component: &Self::Component, out: &mut Vec<u8>);
+    /// Entities inside the Component should be mapped to the client Entities with `map`.
+    fn decode(&self, bytes: &[u8], map: &EntityMap) -> Result<Self::Component, ReplicationError>;
+}
+
+/// Sends the raw bytes of a [Pod] Component.
+pub struct PodCodec<T: Pod>(PhantomData<fn() -> T>);
+
+impl<T: Pod> Default for PodCodec<T> {
+    fn default() -> Self {
+        PodCodec(PhantomData)
+    }
+}
+
+impl<T: Pod> ComponentCodec for PodCodec<T> {
+    type Component = T;
+
+    fn encode(&self, component: &T, out: &mut Vec<u8>) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                (component as *const T).cast::<u8>(),
+                std::mem::size_of::<T>(),
+            )
+        };
+        out.extend_from_slice(bytes);
+    }
+
+    fn decode(&self, bytes: &[u8], _map: &EntityMap) -> Result<T, ReplicationError> {
+        if bytes.len() != std::mem::size_of::<T>() {
+            return Err(ReplicationError::Malformed);
+        }
+        Ok(unsafe { bytes.as_ptr().cast::<T>().read_unaligned() })
+    }
+}
+
+pub(crate) trait ErasedCodec: Send + Sync {
+    fn info(&self) -> ComponentInfo;
+    /// # Safety
+    /// `component` must point to the Component of this codec
+    unsafe fn encode(&self, component: *const u8, out: &mut Vec<u8>);
+    fn decode(
+        &self,
+        bytes: &[u8],
+        map: &EntityMap,
+    ) -> Result<Box<dyn Any + Send + Sync>, ReplicationError>;
+}
+
+impl<C: ComponentCodec> ErasedCodec for C {
+    fn info(&self) -> ComponentInfo {
+        ComponentInfo::new::<C::Component>()
+    }
+
+    unsafe fn encode(&self, component: *const u8, out: &mut Vec<u8>) {
+        ComponentCodec::encode(self, &*component.cast::<C::Component>(), out)
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        map: &EntityMap,
+    ) -> Result<Box<dyn Any + Send + Sync>, ReplicationError> {
+        Ok(Box::new(ComponentCodec::decode(self, bytes, map)?))
+    }
+}
+
+/// The codecs of the replicated Components.
+///
+/// Components are identified on the wire by the order they were registered in.
+#[derive(Default)]
+pub struct ReplicationRegistry {
+    codecs: Vec<(ComponentInfo, Box<dyn ErasedCodec>)>,
+    ids: HashMap<TypeId, u16>,
+}
+
+impl Debug for ReplicationRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.codecs.iter().map(|(info, _)| info))
+            .finish()
+    }
+}
+
+impl ReplicationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers the codec for its Component.
+    ///
+    /// # Panics
+    /// If the Component already has a codec or more than u16::MAX codecs are registered.
+    pub fn register<C: ComponentCodec>(&mut self, codec: C) -> &mut Self {
+        let id = u16::try_from(self.codecs.len()).expect("Too many replicated components");
+        let info = ComponentInfo::new::<C::Component>();
+        if self.ids.insert(info.id, id).is_some() {
+            panic!("Component already has a codec {:?}", info);
+        }
+        self.codecs.push((info, Box::new(codec)));
+        self
+    }
+    pub fn is_registered<T: Component>(&self) -> bool {
+        self.ids.contains_key(&TypeId::of::<T>())
+    }
+
+    pub(crate) fn id_of(&self, typ: &TypeId) -> Option<u16> {
+        self.ids.get(typ).copied()
+    }
+    pub(crate) fn codec(
+        &self,
+        id: u16,
+    ) -> Result<&(ComponentInfo, Box<dyn ErasedCodec>), ReplicationError> {
+        self.codecs
+            .get(id as usize)
+            .ok_or(ReplicationError::UnknownComponent(id))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationError {
+    /// The packet could not be decoded
+    Malformed,
+    /// The packet contains a component id without a codec
+    UnknownComponent(u16),
+    /// A replicated component is currently borrowed
+    ComponentLocked,
+    World(WorldError),
+}
+
+impl Display for ReplicationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplicationError::Malformed => write!(f, "Malformed packet"),
+            ReplicationError::UnknownComponent(id) => write!(f, "Unknown component {}", id),
+            ReplicationError::ComponentLocked => write!(f, "Component is locked"),
+            ReplicationError::World(error) => Display::fmt(error, f),
+        }
+    }
+}
+
+impl std::error::Error for ReplicationError {}
+
+impl From<WorldError> for ReplicationError {
+    fn from(error: WorldError) -> Self {
+        ReplicationError::World(error)
+    }
+}
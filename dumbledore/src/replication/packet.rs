@@ -0,0 +1,148 @@
+use crate::entities::entity::Entity;
+use crate::replication::ReplicationError;
+use std::num::NonZeroU32;
+
+/// The changes to send to a client for one tick.
+///
+/// Entities are the Entities of the server World.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeltaPacket {
+    /// The change tick the packet was made at.
+    pub tick: u32,
+    /// Entities new to the client or that moved to another Archetype. Contains every replicated component.
+    pub spawned: Vec<EntityUpdate>,
+    /// Entities the client knows about. Contains only the components that changed.
+    pub changed: Vec<EntityUpdate>,
+    /// Entities that left the interest set or were removed from the World.
+    pub despawned: Vec<Entity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityUpdate {
+    pub entity: Entity,
+    pub archetype: u32,
+    /// The replicated component id and the encoded component.
+    pub components: Vec<(u16, Vec<u8>)>,
+}
+
+impl DeltaPacket {
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.changed.is_empty() && self.despawned.is_empty()
+    }
+    /// Appends the packet to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.tick.to_le_bytes());
+        for updates in [&self.spawned, &self.changed] {
+            out.extend_from_slice(&(updates.len() as u32).to_le_bytes());
+            for update in updates.iter() {
+                write_entity(out, &update.entity);
+                out.extend_from_slice(&update.archetype.to_le_bytes());
+                out.extend_from_slice(&(update.components.len() as u16).to_le_bytes());
+                for (id, bytes) in update.components.iter() {
+                    out.extend_from_slice(&id.to_le_bytes());
+                    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                    out.extend_from_slice(bytes);
+                }
+            }
+        }
+        out.extend_from_slice(&(self.despawned.len() as u32).to_le_bytes());
+        for entity in self.despawned.iter() {
+            write_entity(out, entity);
+        }
+    }
+    /// Reads a packet written by [DeltaPacket::write]
+    pub fn read(bytes: &[u8]) -> Result<Self, ReplicationError> {
+        let mut reader = Reader(bytes);
+        let tick = reader.u32()?;
+        let spawned = reader.updates()?;
+        let changed = reader.updates()?;
+        let mut despawned = Vec::new();
+        for _ in 0..reader.u32()? {
+            despawned.push(reader.entity()?);
+        }
+        reader.finish()?;
+        Ok(DeltaPacket {
+            tick,
+            spawned,
+            changed,
+            despawned,
+        })
+    }
+}
+
+fn write_entity(out: &mut Vec<u8>, entity: &Entity) {
+    out.extend_from_slice(&entity.id.to_le_bytes());
+    out.extend_from_slice(&entity.generation.get().to_le_bytes());
+}
+
+/// Reads little endian values from a byte slice. Shared with [crate::snapshot::binary]
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+/// The input ended early or had bytes left over.
+pub(crate) struct Truncated;
+
+impl From<Truncated> for ReplicationError {
+    fn from(_: Truncated) -> Self {
+        ReplicationError::Malformed
+    }
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], Truncated> {
+        if self.0.len() < len {
+            return Err(Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, Truncated> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub(crate) fn u16(&mut self) -> Result<u16, Truncated> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+    pub(crate) fn u32(&mut self) -> Result<u32, Truncated> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    /// A u32 length followed by that many u32s.
+    pub(crate) fn u32_vec(&mut self) -> Result<Vec<u32>, Truncated> {
+        let len = self.u32()? as usize;
+        if self.0.len() / 4 < len {
+            return Err(Truncated);
+        }
+        (0..len).map(|_| self.u32()).collect()
+    }
+    /// Fails if any bytes are left.
+    pub(crate) fn finish(self) -> Result<(), Truncated> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Truncated)
+        }
+    }
+    fn entity(&mut self) -> Result<Entity, ReplicationError> {
+        let id = self.u32()?;
+        let generation = NonZeroU32::new(self.u32()?).ok_or(ReplicationError::Malformed)?;
+        Ok(Entity { generation, id })
+    }
+    fn updates(&mut self) -> Result<Vec<EntityUpdate>, ReplicationError> {
+        let mut updates = Vec::new();
+        for _ in 0..self.u32()? {
+            let entity = self.entity()?;
+            let archetype = self.u32()?;
+            let mut components = Vec::new();
+            for _ in 0..self.u16()? {
+                let id = self.u16()?;
+                let len = self.u32()? as usize;
+                components.push((id, self.bytes(len)?.to_vec()));
+            }
+            updates.push(EntityUpdate {
+                entity,
+                archetype,
+                components,
+            });
+        }
+        Ok(updates)
+    }
+}
@@ -0,0 +1,155 @@
+use crate::archetypes::arche::Archetype;
+use crate::entities::entity::{Entity, EntityLocation};
+use crate::replication::packet::{DeltaPacket, EntityUpdate};
+use crate::replication::ReplicationRegistry;
+use crate::world::World;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+struct KnownEntity {
+    archetype: u32,
+    /// The change tick the client was last sent this entity at.
+    tick: u32,
+}
+
+/// The replication state of one client on the server.
+///
+/// Remembers which Entities the client knows about so only the differences are sent.
+#[derive(Debug, Clone, Default)]
+pub struct ClientReplicator {
+    known: HashMap<Entity, KnownEntity>,
+    /// The change tick of the last packet.
+    last_tick: Option<u32>,
+}
+
+impl ClientReplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The Entities the client currently knows about.
+    pub fn known(&self) -> impl Iterator<Item = &Entity> {
+        self.known.keys()
+    }
+    /// The change tick the last packet was encoded at.
+    pub fn last_tick(&self) -> Option<u32> {
+        self.last_tick
+    }
+    /// Forgets everything sent so far. The next packet will spawn the whole interest set again.
+    pub fn reset(&mut self) {
+        self.known.clear();
+        self.last_tick = None;
+    }
+    /// Produces the packet that brings the client up to date with the Entities in `interest`.
+    ///
+    /// Changes are detected with the change tick of the World, which is left as it is.
+    /// Call [World::increment_change_tick] once after encoding the packets of every client,
+    /// otherwise changes made later in the same tick are never sent.
+    ///
+    /// Entities with a replicated component that is mutably borrowed are skipped until the next call.
+    ///
+    /// # Panics
+    /// In debug builds, if the change tick did not advance since the last packet.
+    pub fn encode_delta(
+        &mut self,
+        world: &World,
+        registry: &ReplicationRegistry,
+        interest: impl IntoIterator<Item = Entity>,
+    ) -> DeltaPacket {
+        let tick = world.change_tick();
+        debug_assert!(
+            !matches!(self.last_tick, Some(last) if last >= tick),
+            "The change tick has to advance between packets"
+        );
+        self.last_tick = Some(tick);
+        let mut packet = DeltaPacket {
+            tick,
+            ..DeltaPacket::default()
+        };
+        let mut seen = HashSet::new();
+        for entity in interest {
+            let Some((current, Some(location))) = world.entities.get_entity(entity.id) else {
+                continue;
+            };
+            if current != entity {
+                continue;
+            }
+            let Some(archetype) = world.archetypes.get(&location.archetype) else {
+                continue;
+            };
+            if !seen.insert(entity.clone()) {
+                continue;
+            }
+            match self.known.get_mut(&entity) {
+                Some(known) if known.archetype == location.archetype => {
+                    let since = known.tick;
+                    let Some(components) = encode(archetype, &location, registry, Some(since))
+                    else {
+                        continue;
+                    };
+                    known.tick = tick;
+                    if !components.is_empty() {
+                        packet.changed.push(EntityUpdate {
+                            entity,
+                            archetype: location.archetype,
+                            components,
+                        });
+                    }
+                }
+                _ => {
+                    let Some(components) = encode(archetype, &location, registry, None) else {
+                        continue;
+                    };
+                    self.known.insert(
+                        entity.clone(),
+                        KnownEntity {
+                            archetype: location.archetype,
+                            tick,
+                        },
+                    );
+                    packet.spawned.push(EntityUpdate {
+                        entity,
+                        archetype: location.archetype,
+                        components,
+                    });
+                }
+            }
+        }
+        self.known.retain(|entity, _| {
+            if seen.contains(entity) {
+                true
+            } else {
+                packet.despawned.push(entity.clone());
+                false
+            }
+        });
+        packet
+    }
+}
+
+/// Encodes the replicated components changed after `since` or all of them if `since` is None.
+///
+/// Returns None if one of them is locked.
+fn encode(
+    archetype: &Archetype,
+    location: &EntityLocation,
+    registry: &ReplicationRegistry,
+    since: Option<u32>,
+) -> Option<Vec<(u16, Vec<u8>)>> {
+    let mut components = Vec::new();
+    for info in archetype.0.components.iter() {
+        let Some(id) = registry.id_of(&info.id) else {
+            continue;
+        };
+        if let Some(since) = since {
+            if archetype.changed_tick_raw(location.index, &info.id)? <= since {
+                continue;
+            }
+        }
+        let raw = archetype.get_raw(location.index, &info.id).ok()??;
+        let (_, codec) = registry.codec(id).ok()?;
+        let mut bytes = Vec::new();
+        unsafe { codec.encode(raw.component, &mut bytes) };
+        components.push((id, bytes));
+    }
+    Some(components)
+}
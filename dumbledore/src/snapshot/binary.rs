@@ -0,0 +1,446 @@
+//! Compact binary snapshots of a [World].
+//!
+//! Restoring a binary snapshot gives back the exact same World. Entity IDs, generations and free lists are kept,
+//! so Entities held outside of the World stay valid.
+//!
+//! Layout, all integers are little endian:
+//! ```no_lang
+//! | magic "DMBW" | version u16 |
+//! | header: archetypes and their component schemas |
+//! | entity table: generations, locations and the free list |
+//! | one block per archetype: slot entity IDs then one column per component |
+//! ```
+//! Every section is written as `| length u64 | payload | crc32 u32 |`
+use crate::archetypes::arche::{Archetype, ArchetypeInner};
+use crate::archetypes::ComponentInfo;
+use crate::component::Component;
+use crate::entities::entity::{EntityLocation, EntityMeta};
+use crate::entities::entity_set::{EntitySet, EntitySetInner};
+use crate::replication::packet::{Reader, Truncated};
+use crate::world::World;
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{Read, Write};
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"DMBW";
+pub const VERSION: u16 = 2;
+/// The most unused capacity allocated up front for each Archetype and the entity table when reading a snapshot.
+///
+/// A snapshot can come from an untrusted source, so only the rows it actually contains are trusted. Capacity beyond
+/// that is restored up to this limit, and the World grows as usual after loading.
+pub const MAX_SPARE_CAPACITY: u32 = 1 << 20;
+
+/// A Component that can be copied byte for byte.
+///
+/// # Safety
+/// The type must not contain padding, pointers or references and every bit pattern written by this program must be valid.
+pub unsafe trait Pod: Component + Copy {}
+
+#[derive(Debug, Clone)]
+struct PodComponent {
+    name: String,
+    info: ComponentInfo,
+}
+
+/// The Components that can be written into a binary snapshot.
+///
+/// Components are matched by the name they are registered with. So the name must stay the same between versions.
+#[derive(Debug, Clone, Default)]
+pub struct BinaryRegistry {
+    components: HashMap<TypeId, PodComponent>,
+    names: HashMap<String, TypeId>,
+}
+
+impl BinaryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a Component under the given name.
+    pub fn register<T: Pod>(&mut self, name: impl Into<String>) -> &mut Self {
+        let name = name.into();
+        if let Some(old) = self.components.get(&TypeId::of::<T>()) {
+            self.names.remove(&old.name);
+        }
+        self.names.insert(name.clone(), TypeId::of::<T>());
+        self.components.insert(
+            TypeId::of::<T>(),
+            PodComponent {
+                name,
+                info: ComponentInfo::new::<T>(),
+            },
+        );
+        self
+    }
+    pub fn is_registered<T: Component>(&self) -> bool {
+        self.components.contains_key(&TypeId::of::<T>())
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    /// The data is not a binary snapshot
+    BadMagic,
+    /// The snapshot was written by a newer version
+    UnsupportedVersion(u16),
+    /// A section did not match its checksum
+    ChecksumMismatch,
+    /// The snapshot contains a component that is not registered
+    UnknownComponent(String),
+    /// The registered component does not have the size or alignment in the snapshot
+    SchemaMismatch(String),
+    /// The Archetype contains a component that is not registered
+    UnregisteredArchetype(u32),
+    /// A component is currently mutably borrowed
+    ComponentLocked,
+    /// The snapshot is internally inconsistent
+    Corrupt,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(error) => Display::fmt(error, f),
+            SnapshotError::BadMagic => write!(f, "Not a binary snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot version {}", version)
+            }
+            SnapshotError::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            SnapshotError::UnknownComponent(name) => write!(f, "Unknown component {}", name),
+            SnapshotError::SchemaMismatch(name) => {
+                write!(f, "Component {} does not match the snapshot", name)
+            }
+            SnapshotError::UnregisteredArchetype(id) => {
+                write!(f, "Archetype {} has unregistered components", id)
+            }
+            SnapshotError::ComponentLocked => write!(f, "A component is locked"),
+            SnapshotError::Corrupt => write!(f, "Snapshot is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(error: std::io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl World {
+    /// Writes the World as a binary snapshot.
+    ///
+    /// # Errors
+    /// If an Archetype has a component that is not registered or a component is mutably borrowed.
+    pub fn write_binary<W: Write>(
+        &self,
+        mut writer: W,
+        registry: &BinaryRegistry,
+    ) -> Result<(), SnapshotError> {
+        let mut header = Vec::new();
+        put_u32(&mut header, self.archetypes.len() as u32);
+        for (id, archetype) in self.archetypes.iter() {
+            let inner = &archetype.0;
+            put_u32(&mut header, *id);
+            put_u32(&mut header, inner.entity_data.len() as u32);
+            put_u32(&mut header, inner.entities_len.load(Ordering::Relaxed));
+            let free_list = inner.free_list.lock().unwrap();
+            put_u32(&mut header, free_list.len() as u32);
+            for index in free_list.iter() {
+                put_u32(&mut header, *index);
+            }
+            drop(free_list);
+            header.push(inner.dense as u8);
+            put_u32(&mut header, inner.components.len() as u32);
+            for info in inner.components.iter() {
+                let component = registry
+                    .components
+                    .get(&info.id)
+                    .ok_or(SnapshotError::UnregisteredArchetype(*id))?;
+                put_u32(&mut header, component.name.len() as u32);
+                header.extend_from_slice(component.name.as_bytes());
+                put_u32(&mut header, info.layout.size() as u32);
+                put_u32(&mut header, info.layout.align() as u32);
+            }
+        }
+
+        let entities = &self.entities.0;
+        let mut table = Vec::new();
+        let length = entities.length.load(Ordering::Relaxed);
+        put_u32(&mut table, entities.capacity.load(Ordering::Acquire));
+        put_u32(&mut table, length);
+        let free_list = entities.free_list();
+        put_u32(&mut table, free_list.len() as u32);
+        for index in free_list {
+            put_u32(&mut table, index);
+        }
+        for slot in entities.slots().take(length as usize) {
+            let meta = slot.meta();
+            put_u32(&mut table, meta.generation.get());
+            table.push(meta.in_use as u8);
+            // Entities without components are written as u32::MAX, u32::MAX
+            let location = meta.location.unwrap_or(EntityLocation {
+                archetype: u32::MAX,
+                index: u32::MAX,
+            });
+            put_u32(&mut table, location.archetype);
+            put_u32(&mut table, location.index);
+        }
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        write_section(&mut writer, &header)?;
+        write_section(&mut writer, &table)?;
+        for archetype in self.archetypes.values() {
+            write_section(&mut writer, &archetype_columns(archetype)?)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a World written by [World::write_binary].
+    ///
+    /// The capacities are restored up to [MAX_SPARE_CAPACITY] past the stored entities.
+    pub fn read_binary<R: Read>(
+        mut reader: R,
+        registry: &BinaryRegistry,
+    ) -> Result<World, SnapshotError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let change_tick = Arc::new(AtomicU32::new(1));
+        let header = read_section(&mut reader)?;
+        let mut header = Reader(&header);
+        let mut archetypes = BTreeMap::new();
+        let mut free_rows = HashSet::new();
+        for _ in 0..header.u32()? {
+            let id = header.u32()?;
+            let capacity = header.u32()?;
+            let entities_len = header.u32()?;
+            let free_list = header.u32_vec()?;
+            let dense = header.u8()? != 0;
+            let mut columns = Vec::new();
+            for _ in 0..header.u32()? {
+                let name = read_string(&mut header)?;
+                let size = header.u32()? as usize;
+                let align = header.u32()? as usize;
+                let component = registry
+                    .names
+                    .get(&name)
+                    .map(|id| &registry.components[id])
+                    .ok_or_else(|| SnapshotError::UnknownComponent(name.clone()))?;
+                if component.info.layout.size() != size || component.info.layout.align() != align {
+                    return Err(SnapshotError::SchemaMismatch(name));
+                }
+                columns.push(component.info.clone());
+            }
+            if entities_len > capacity
+                || !valid_free_list(&free_list, entities_len)
+                || (dense && !free_list.is_empty())
+            {
+                return Err(SnapshotError::Corrupt);
+            }
+            free_rows.extend(free_list.iter().map(|index| (id, *index)));
+            let capacity = capacity.min(entities_len.saturating_add(MAX_SPARE_CAPACITY));
+            let mut inner =
+                ArchetypeInner::new(columns.clone(), capacity as usize, change_tick.clone());
+            inner.dense = dense;
+            inner.entities_len.store(entities_len, Ordering::Relaxed);
+            *inner.free_list.lock().unwrap() = free_list;
+            archetypes.insert(id, (inner, columns));
+        }
+        header.finish()?;
+
+        let table = read_section(&mut reader)?;
+        let mut table = Reader(&table);
+        let capacity = table.u32()?;
+        let length = table.u32()?;
+        let free_list = table.u32_vec()?;
+        if length > capacity || !valid_free_list(&free_list, length) {
+            return Err(SnapshotError::Corrupt);
+        }
+        let inner = EntitySetInner::new(capacity.min(length.saturating_add(MAX_SPARE_CAPACITY)));
+        let free_ids = free_list.iter().copied().collect::<HashSet<_>>();
+        // Every row may only be claimed by one live entity, and none of them may be free
+        let mut used = HashSet::new();
+        for (index, slot) in inner.slots().take(length as usize).enumerate() {
+            let generation = NonZeroU32::new(table.u32()?).ok_or(SnapshotError::Corrupt)?;
+            let in_use = table.u8()? != 0;
+            let location = Some(EntityLocation {
+                archetype: table.u32()?,
+                index: table.u32()?,
+            })
+            .filter(|location| location.archetype != u32::MAX || location.index != u32::MAX);
+            if in_use && free_ids.contains(&(index as u32)) {
+                return Err(SnapshotError::Corrupt);
+            }
+            if let (true, Some(location)) = (in_use, &location) {
+                let (archetype, _) = archetypes
+                    .get(&location.archetype)
+                    .ok_or(SnapshotError::Corrupt)?;
+                let row = (location.archetype, location.index);
+                if location.index >= archetype.entities_len.load(Ordering::Relaxed)
+                    || free_rows.contains(&row)
+                    || !used.insert(row)
+                {
+                    return Err(SnapshotError::Corrupt);
+                }
+            }
+            slot.store_meta(&EntityMeta {
+                generation,
+                in_use,
+                location,
+            });
+        }
+        table.finish()?;
+        inner.length.store(length, Ordering::Relaxed);
+        for index in free_list.into_iter().rev() {
+            inner.push_free(index);
+        }
+
+        let mut world = World {
+            archetypes: BTreeMap::new(),
+            entities: EntitySet(Arc::new(inner)),
+            change_tick,
+            spatial: None,
+            pending: Default::default(),
+            borrow_policy: Default::default(),
+        };
+        for (id, (inner, columns)) in archetypes {
+            let block = read_section(&mut reader)?;
+            let mut block = Reader(&block);
+            let entities_len = inner.entities_len.load(Ordering::Relaxed) as usize;
+            for data in inner.entity_data.iter().take(entities_len) {
+                data.entity_id.store(block.u32()?, Ordering::Relaxed);
+            }
+            for info in columns.iter() {
+                let (offset, _) = *inner.component_offsets.get(&info.id).unwrap();
+                let size = info.layout.size();
+                // Tag components have no bytes to copy
+                if size == 0 {
+                    continue;
+                }
+                let column = block.bytes(size * entities_len)?;
+                for (data, bytes) in inner.entity_data.iter().zip(column.chunks_exact(size)) {
+                    unsafe {
+                        let ptr = data.inner_ptrs.load(Ordering::Relaxed).add(offset);
+                        std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, size);
+                    }
+                }
+            }
+            block.finish()?;
+            world.archetypes.insert(id, Archetype(Arc::new(inner)));
+        }
+        Ok(world)
+    }
+}
+
+/// The slot entity IDs followed by each component column.
+fn archetype_columns(archetype: &Archetype) -> Result<Vec<u8>, SnapshotError> {
+    let inner = &archetype.0;
+    let entities_len = inner.entities_len.load(Ordering::Relaxed);
+    let mut block = Vec::new();
+    for data in inner.entity_data.iter().take(entities_len as usize) {
+        put_u32(&mut block, data.entity_id.load(Ordering::Relaxed));
+    }
+    for info in inner.components.iter() {
+        let size = info.layout.size();
+        for index in 0..entities_len {
+            let raw = archetype
+                .get_raw(index, &info.id)
+                .map_err(|_| SnapshotError::ComponentLocked)?
+                .ok_or(SnapshotError::Corrupt)?;
+            let bytes = unsafe { std::slice::from_raw_parts(raw.component, size) };
+            block.extend_from_slice(bytes);
+        }
+    }
+    Ok(block)
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_section<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), SnapshotError> {
+    writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+    writer.write_all(payload)?;
+    writer.write_all(&crc32(payload).to_le_bytes())?;
+    Ok(())
+}
+
+fn read_section<R: Read>(reader: &mut R) -> Result<Vec<u8>, SnapshotError> {
+    let mut length = [0; 8];
+    reader.read_exact(&mut length)?;
+    let length = u64::from_le_bytes(length);
+    let mut payload = Vec::new();
+    reader.take(length).read_to_end(&mut payload)?;
+    if payload.len() as u64 != length {
+        return Err(SnapshotError::Corrupt);
+    }
+    let mut checksum = [0; 4];
+    reader.read_exact(&mut checksum)?;
+    if crc32(&payload) != u32::from_le_bytes(checksum) {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+    Ok(payload)
+}
+
+impl From<Truncated> for SnapshotError {
+    fn from(_: Truncated) -> Self {
+        SnapshotError::Corrupt
+    }
+}
+
+fn read_string(reader: &mut Reader) -> Result<String, SnapshotError> {
+    let len = reader.u32()? as usize;
+    String::from_utf8(reader.bytes(len)?.to_vec()).map_err(|_| SnapshotError::Corrupt)
+}
+
+/// True if every entry is below `len` and listed once, so no slot can be handed out twice.
+fn valid_free_list(free_list: &[u32], len: u32) -> bool {
+    let mut seen = HashSet::with_capacity(free_list.len());
+    free_list
+        .iter()
+        .all(|index| *index < len && seen.insert(*index))
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE)
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
@@ -0,0 +1,3 @@
+pub mod binary;
+#[cfg(feature = "serde")]
+pub mod serialize;
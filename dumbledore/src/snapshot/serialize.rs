@@ -0,0 +1,435 @@
+//! Human readable snapshots of a [World] using serde.
+//!
+//! The snapshot is a sequence of entities. Each entity is written as
+//! ```no_lang
+//! { entity: { generation, id }, location: { archetype, index }, components: { name: value, ... } }
+//! ```
+//! Entities without components are written with no location and no components.
+//! Only archetypes where every component is registered in the [SerdeRegistry] are written.
+//! Entities in other archetypes are treated as transient and skipped.
+use crate::archetypes::arche::Archetype;
+use crate::archetypes::ComponentInfo;
+use crate::component::Component;
+use crate::entities::entity::{Entity, EntityLocation};
+use crate::entities::entity_map::{EntityMap, MapEntities};
+use crate::world::{World, WorldError};
+use serde::de::{DeserializeOwned, DeserializeSeed, Error as _, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error as _, SerializeMap, SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::Ordering;
+
+type SerializeFn = unsafe fn(*const u8) -> *const dyn erased_serde::Serialize;
+type DeserializeFn = for<'de> fn(
+    &mut dyn erased_serde::Deserializer<'de>,
+) -> Result<Box<dyn Any + Send + Sync>, erased_serde::Error>;
+type MapEntitiesFn = fn(&mut (dyn Any + Send + Sync), &EntityMap);
+
+#[derive(Clone)]
+struct SerdeComponent {
+    name: String,
+    info: ComponentInfo,
+    serialize: SerializeFn,
+    deserialize: DeserializeFn,
+    map_entities: Option<MapEntitiesFn>,
+}
+
+/// The Components that are written into a snapshot.
+///
+/// Components are written under the name they are registered with. So the name must stay the same between versions.
+#[derive(Clone, Default)]
+pub struct SerdeRegistry {
+    components: HashMap<TypeId, SerdeComponent>,
+    names: HashMap<String, TypeId>,
+}
+
+impl Debug for SerdeRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.names.keys()).finish()
+    }
+}
+
+impl SerdeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a Component under the given name.
+    pub fn register<T: Component + Serialize + DeserializeOwned>(
+        &mut self,
+        name: impl Into<String>,
+    ) -> &mut Self {
+        self.insert::<T>(name.into(), None)
+    }
+    /// Registers a Component that stores Entities.
+    ///
+    /// [MapEntities::map_entities] is called on it after it is loaded.
+    pub fn register_mapped<T: Component + Serialize + DeserializeOwned + MapEntities>(
+        &mut self,
+        name: impl Into<String>,
+    ) -> &mut Self {
+        fn map_entities<T: MapEntities + 'static>(
+            value: &mut (dyn Any + Send + Sync),
+            map: &EntityMap,
+        ) {
+            if let Some(value) = value.downcast_mut::<T>() {
+                value.map_entities(map);
+            }
+        }
+        self.insert::<T>(name.into(), Some(map_entities::<T>))
+    }
+    pub fn is_registered<T: Component>(&self) -> bool {
+        self.components.contains_key(&TypeId::of::<T>())
+    }
+
+    fn insert<T: Component + Serialize + DeserializeOwned>(
+        &mut self,
+        name: String,
+        map_entities: Option<MapEntitiesFn>,
+    ) -> &mut Self {
+        unsafe fn serialize<T: Serialize + 'static>(
+            ptr: *const u8,
+        ) -> *const dyn erased_serde::Serialize {
+            ptr.cast::<T>()
+        }
+        fn deserialize<T: DeserializeOwned + Send + Sync + 'static>(
+            deserializer: &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<Box<dyn Any + Send + Sync>, erased_serde::Error> {
+            Ok(Box::new(erased_serde::deserialize::<T>(deserializer)?))
+        }
+        if let Some(old) = self.components.get(&TypeId::of::<T>()) {
+            self.names.remove(&old.name);
+        }
+        self.names.insert(name.clone(), TypeId::of::<T>());
+        self.components.insert(
+            TypeId::of::<T>(),
+            SerdeComponent {
+                name,
+                info: ComponentInfo::new::<T>(),
+                serialize: serialize::<T>,
+                deserialize: deserialize::<T>,
+                map_entities,
+            },
+        );
+        self
+    }
+    fn by_name(&self, name: &str) -> Option<&SerdeComponent> {
+        self.names.get(name).and_then(|id| self.components.get(id))
+    }
+    fn covers(&self, archetype: &Archetype) -> bool {
+        archetype
+            .0
+            .components
+            .iter()
+            .all(|c| self.components.contains_key(&c.id))
+    }
+}
+
+impl World {
+    /// Writes every Entity in an Archetype covered by the registry.
+    ///
+    /// # Errors
+    /// If a Component is currently mutably borrowed.
+    pub fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+        registry: &SerdeRegistry,
+    ) -> Result<S::Ok, S::Error> {
+        WorldSer {
+            world: self,
+            registry,
+        }
+        .serialize(serializer)
+    }
+    /// Loads the Entities of a snapshot into this World.
+    ///
+    /// Every Entity is given a new ID. Components registered with [SerdeRegistry::register_mapped] are remapped to the new Entities.
+    ///
+    /// # Returns
+    /// The map from the Entities in the snapshot to the Entities in this World.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+        registry: &SerdeRegistry,
+    ) -> Result<EntityMap, D::Error> {
+        let loaded = WorldSeed(registry).deserialize(deserializer)?;
+        self.load_entities(loaded, registry)
+            .map_err(D::Error::custom)
+    }
+
+    fn load_entities(
+        &mut self,
+        loaded: Vec<LoadedEntity>,
+        registry: &SerdeRegistry,
+    ) -> Result<EntityMap, WorldError> {
+        let mut archetypes: BTreeMap<u32, (Vec<ComponentInfo>, usize)> = BTreeMap::new();
+        for entity in loaded.iter() {
+            let mut infos = entity
+                .components
+                .iter()
+                .map(|(id, _)| registry.components[id].info.clone())
+                .collect::<Vec<_>>();
+            infos.sort_unstable_by_key(|c| c.id);
+            let Some(location) = &entity.location else {
+                if !infos.is_empty() {
+                    return Err(WorldError::ArchetypeMismatch);
+                }
+                continue;
+            };
+            let (expected, count) = archetypes
+                .entry(location.archetype)
+                .or_insert_with(|| (infos.clone(), 0));
+            if *expected != infos {
+                return Err(WorldError::ArchetypeMismatch);
+            }
+            *count += 1;
+        }
+        for (id, (infos, count)) in archetypes {
+            self.ensure_archetype(id, &infos, count)?;
+        }
+        self.ensure_entity_capacity(loaded.len())?;
+
+        let mut map = EntityMap::new();
+        for entity in loaded.iter() {
+            map.insert(entity.entity.clone(), self.entities.alloc());
+        }
+        for entity in loaded {
+            let components = entity
+                .components
+                .into_iter()
+                .map(|(id, mut value)| {
+                    let component = &registry.components[&id];
+                    if let Some(map_entities) = component.map_entities {
+                        map_entities(value.as_mut(), &map);
+                    }
+                    (component.info.clone(), value)
+                })
+                .collect();
+            if let Some(location) = entity.location {
+                self.add_entity_boxed(&map.map(&entity.entity), location.archetype, components);
+            }
+        }
+        Ok(map)
+    }
+}
+
+struct WorldSer<'a> {
+    world: &'a World,
+    registry: &'a SerdeRegistry,
+}
+
+impl Serialize for WorldSer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entities = &self.world.entities;
+        let length = entities.0.length.load(Ordering::Relaxed);
+        let mut records = Vec::new();
+        for id in 0..length {
+            let Some((entity, location)) = entities.get_entity(id) else {
+                continue;
+            };
+            let archetype = match &location {
+                Some(location) => match self.world.archetypes.get(&location.archetype) {
+                    Some(archetype) if self.registry.covers(archetype) => Some(archetype),
+                    _ => continue,
+                },
+                None => None,
+            };
+            records.push(EntitySer {
+                entity,
+                location,
+                archetype,
+                registry: self.registry,
+            });
+        }
+        let mut seq = serializer.serialize_seq(Some(records.len()))?;
+        for record in records.iter() {
+            seq.serialize_element(record)?;
+        }
+        seq.end()
+    }
+}
+
+struct EntitySer<'a> {
+    entity: Entity,
+    location: Option<EntityLocation>,
+    archetype: Option<&'a Archetype>,
+    registry: &'a SerdeRegistry,
+}
+
+impl Serialize for EntitySer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Entity", 3)?;
+        state.serialize_field("entity", &self.entity)?;
+        state.serialize_field("location", &self.location)?;
+        state.serialize_field("components", &ComponentsSer(self))?;
+        state.end()
+    }
+}
+
+struct ComponentsSer<'a>(&'a EntitySer<'a>);
+
+impl Serialize for ComponentsSer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let EntitySer {
+            location: Some(location),
+            archetype: Some(archetype),
+            registry,
+            ..
+        } = self.0
+        else {
+            return serializer.serialize_map(Some(0))?.end();
+        };
+        let mut map = serializer.serialize_map(Some(archetype.0.components.len()))?;
+        for info in archetype.0.components.iter() {
+            let component = &registry.components[&info.id];
+            let raw = archetype
+                .get_raw(location.index, &info.id)
+                .map_err(|_| S::Error::custom(format!("{} is locked", component.name)))?
+                .ok_or_else(|| S::Error::custom(format!("{} is missing", component.name)))?;
+            let value = unsafe { &*(component.serialize)(raw.component) };
+            map.serialize_entry(&component.name, value)?;
+        }
+        map.end()
+    }
+}
+
+struct LoadedEntity {
+    entity: Entity,
+    location: Option<EntityLocation>,
+    components: Vec<(TypeId, Box<dyn Any + Send + Sync>)>,
+}
+
+struct WorldSeed<'a>(&'a SerdeRegistry);
+
+impl<'de> DeserializeSeed<'de> for WorldSeed<'_> {
+    type Value = Vec<LoadedEntity>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for WorldSeed<'_> {
+    type Value = Vec<LoadedEntity>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a sequence of entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut entities = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(entity) = seq.next_element_seed(EntitySeed(self.0))? {
+            entities.push(entity);
+        }
+        Ok(entities)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum EntityField {
+    Entity,
+    Location,
+    Components,
+}
+
+struct EntitySeed<'a>(&'a SerdeRegistry);
+
+impl<'de> DeserializeSeed<'de> for EntitySeed<'_> {
+    type Value = LoadedEntity;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct("Entity", &["entity", "location", "components"], self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntitySeed<'_> {
+    type Value = LoadedEntity;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("an entity")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let entity = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let location = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        let components = seq
+            .next_element_seed(ComponentsSeed(self.0))?
+            .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+        Ok(LoadedEntity {
+            entity,
+            location,
+            components,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entity = None;
+        let mut location = None;
+        let mut components = None;
+        while let Some(key) = map.next_key()? {
+            match key {
+                EntityField::Entity => entity = Some(map.next_value()?),
+                EntityField::Location => location = Some(map.next_value()?),
+                EntityField::Components => {
+                    components = Some(map.next_value_seed(ComponentsSeed(self.0))?)
+                }
+            }
+        }
+        Ok(LoadedEntity {
+            entity: entity.ok_or_else(|| A::Error::missing_field("entity"))?,
+            location: location.ok_or_else(|| A::Error::missing_field("location"))?,
+            components: components.ok_or_else(|| A::Error::missing_field("components"))?,
+        })
+    }
+}
+
+struct ComponentsSeed<'a>(&'a SerdeRegistry);
+
+impl<'de> DeserializeSeed<'de> for ComponentsSeed<'_> {
+    type Value = Vec<(TypeId, Box<dyn Any + Send + Sync>)>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ComponentsSeed<'_> {
+    type Value = Vec<(TypeId, Box<dyn Any + Send + Sync>)>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a map of components")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut components = Vec::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some(name) = map.next_key::<String>()? {
+            let component = self
+                .0
+                .by_name(&name)
+                .ok_or_else(|| A::Error::custom(format!("unknown component {}", name)))?;
+            if components.iter().any(|(id, _)| *id == component.info.id) {
+                return Err(A::Error::custom(format!("duplicate component {}", name)));
+            }
+            let value = map.next_value_seed(ComponentSeed(component))?;
+            components.push((component.info.id, value));
+        }
+        Ok(components)
+    }
+}
+
+struct ComponentSeed<'a>(&'a SerdeComponent);
+
+impl<'de> DeserializeSeed<'de> for ComponentSeed<'_> {
+    type Value = Box<dyn Any + Send + Sync>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut erased = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0.deserialize)(&mut erased).map_err(D::Error::custom)
+    }
+}
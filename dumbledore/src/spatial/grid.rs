@@ -0,0 +1,160 @@
+use crate::entities::entity::Entity;
+use std::collections::HashMap;
+
+type Cell = (i32, i32, i32);
+
+/// A uniform grid of cubes with `cell_size` sides.
+///
+/// Each Entity is stored in the cell containing its position.
+#[derive(Debug, Clone)]
+pub struct UniformGrid {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<Entity>>,
+    positions: HashMap<Entity, [f32; 3]>,
+}
+
+impl UniformGrid {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "cell_size must be positive");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            positions: HashMap::new(),
+        }
+    }
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+    pub fn contains(&self, entity: &Entity) -> bool {
+        self.positions.contains_key(entity)
+    }
+    pub fn position(&self, entity: &Entity) -> Option<[f32; 3]> {
+        self.positions.get(entity).copied()
+    }
+    pub fn entities(&self) -> impl Iterator<Item = (&Entity, &[f32; 3])> {
+        self.positions.iter()
+    }
+    /// Inserts the Entity or moves it if it is already in the grid.
+    pub fn insert(&mut self, entity: Entity, position: [f32; 3]) {
+        let cell = self.cell_of(position);
+        if let Some(old) = self.positions.insert(entity.clone(), position) {
+            let old_cell = self.cell_of(old);
+            if old_cell == cell {
+                return;
+            }
+            self.remove_from_cell(old_cell, &entity);
+        }
+        self.cells.entry(cell).or_default().push(entity);
+    }
+    pub fn remove(&mut self, entity: &Entity) -> Option<[f32; 3]> {
+        let position = self.positions.remove(entity)?;
+        self.remove_from_cell(self.cell_of(position), entity);
+        Some(position)
+    }
+    /// Calls `f` for every Entity inside the box from `min` to `max` inclusive.
+    pub fn for_each_in_aabb(
+        &self,
+        min: [f32; 3],
+        max: [f32; 3],
+        mut f: impl FnMut(&Entity, [f32; 3]),
+    ) {
+        if (0..3).any(|i| min[i] > max[i]) {
+            return;
+        }
+        let (low, high) = (self.cell_of(min), self.cell_of(max));
+        let mut visit = |entities: &Vec<Entity>| {
+            for entity in entities {
+                let position = self.positions[entity];
+                if (0..3).all(|i| position[i] >= min[i] && position[i] <= max[i]) {
+                    f(entity, position);
+                }
+            }
+        };
+        let cells_in_box = [(low.0, high.0), (low.1, high.1), (low.2, high.2)]
+            .iter()
+            .map(|(low, high)| (*high as i64 - *low as i64 + 1) as u64)
+            .fold(1u64, u64::saturating_mul);
+        // Large boxes over a sparse grid are cheaper to answer by walking the occupied cells
+        if cells_in_box > self.cells.len() as u64 {
+            for (cell, entities) in self.cells.iter() {
+                if (low.0..=high.0).contains(&cell.0)
+                    && (low.1..=high.1).contains(&cell.1)
+                    && (low.2..=high.2).contains(&cell.2)
+                {
+                    visit(entities);
+                }
+            }
+            return;
+        }
+        for x in low.0..=high.0 {
+            for y in low.1..=high.1 {
+                for z in low.2..=high.2 {
+                    if let Some(entities) = self.cells.get(&(x, y, z)) {
+                        visit(entities);
+                    }
+                }
+            }
+        }
+    }
+    /// Calls `f` for every Entity within `radius` of `center`.
+    pub fn for_each_in_radius(
+        &self,
+        center: [f32; 3],
+        radius: f32,
+        mut f: impl FnMut(&Entity, [f32; 3]),
+    ) {
+        let min = center.map(|c| c - radius);
+        let max = center.map(|c| c + radius);
+        let radius_squared = radius * radius;
+        self.for_each_in_aabb(min, max, |entity, position| {
+            if distance_squared(center, position) <= radius_squared {
+                f(entity, position);
+            }
+        });
+    }
+
+    /// The `k` Entities closest to `center`. Closest first.
+    pub fn nearest_k(&self, center: [f32; 3], k: usize) -> Vec<Entity> {
+        if k == 0 || self.is_empty() {
+            return Vec::new();
+        }
+        let mut radius = self.cell_size;
+        loop {
+            let mut found = Vec::new();
+            self.for_each_in_radius(center, radius, |entity, position| {
+                found.push((distance_squared(center, position), entity.clone()));
+            });
+            // Everything outside the radius is further away than everything found inside it
+            if found.len() >= k || found.len() == self.len() || !radius.is_finite() {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0));
+                return found.into_iter().take(k).map(|(_, e)| e).collect();
+            }
+            radius *= 2.0;
+        }
+    }
+
+    fn cell_of(&self, position: [f32; 3]) -> Cell {
+        let [x, y, z] = position.map(|v| (v / self.cell_size).floor() as i32);
+        (x, y, z)
+    }
+    fn remove_from_cell(&mut self, cell: Cell, entity: &Entity) {
+        if let Some(entities) = self.cells.get_mut(&cell) {
+            if let Some(index) = entities.iter().position(|e| e == entity) {
+                entities.swap_remove(index);
+            }
+            if entities.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+}
+
+pub(crate) fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
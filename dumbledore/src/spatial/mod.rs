@@ -0,0 +1,219 @@
+//! Indexes Entities by the position stored in one of their Components.
+use crate::component::Component;
+use crate::entities::entity::Entity;
+use crate::spatial::grid::UniformGrid;
+use crate::world::{World, WorldError};
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+pub mod grid;
+
+/// A Component that places its Entity in space.
+pub trait Spatial: Component {
+    fn position(&self) -> [f32; 3];
+}
+
+/// A [UniformGrid] of every Entity with the Component `P`.
+///
+/// Kept up to date by [SpatialIndex::sync] which only reads the positions that changed since the last sync.
+pub struct SpatialIndex<P: Spatial> {
+    grid: UniformGrid,
+    last_tick: u32,
+    /// Entities that were locked during the last sync and need to be read again.
+    stale: HashSet<Entity>,
+    _position: PhantomData<fn() -> P>,
+}
+
+impl<P: Spatial> Debug for SpatialIndex<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpatialIndex")
+            .field("component", &std::any::type_name::<P>())
+            .field("grid", &self.grid)
+            .field("last_tick", &self.last_tick)
+            .finish()
+    }
+}
+
+impl<P: Spatial> Clone for SpatialIndex<P> {
+    fn clone(&self) -> Self {
+        Self {
+            grid: self.grid.clone(),
+            last_tick: self.last_tick,
+            stale: self.stale.clone(),
+            _position: PhantomData,
+        }
+    }
+}
+
+impl<P: Spatial> SpatialIndex<P> {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            grid: UniformGrid::new(cell_size),
+            last_tick: 0,
+            stale: HashSet::new(),
+            _position: PhantomData,
+        }
+    }
+    pub fn grid(&self) -> &UniformGrid {
+        &self.grid
+    }
+    pub fn position(&self, entity: &Entity) -> Option<[f32; 3]> {
+        self.grid.position(entity)
+    }
+    /// Brings the index up to date with the World.
+    ///
+    /// Reads the change tick of the World without advancing it.
+    /// Positions changed in the current tick are read again on the next sync, as they can still change before the tick advances.
+    ///
+    /// # Returns
+    /// The Entities that were removed because they no longer exist.
+    pub fn sync(&mut self, world: &World) -> Vec<Entity> {
+        let tick = world.change_tick();
+        let removed = self
+            .grid
+            .entities()
+            .filter(|(entity, _)| !world.entities.is_alive(entity))
+            .map(|(entity, _)| entity.clone())
+            .collect::<Vec<_>>();
+        for entity in removed.iter() {
+            self.grid.remove(entity);
+            self.stale.remove(entity);
+        }
+
+        let typ = TypeId::of::<P>();
+        for (archetype_id, archetype) in world.archetypes.iter() {
+            if archetype.0.component_offsets.get(&typ).is_none() {
+                continue;
+            }
+            let free_list = archetype
+                .0
+                .free_list
+                .lock()
+                .unwrap()
+                .iter()
+                .copied()
+                .collect::<HashSet<_>>();
+            let entities_len = archetype.0.entities_len.load(Ordering::Relaxed);
+            for index in (0..entities_len).filter(|index| !free_list.contains(index)) {
+                let id = archetype.0.entity_data[index as usize]
+                    .entity_id
+                    .load(Ordering::Relaxed);
+                let Some((entity, Some(location))) = world.entities.get_entity(id) else {
+                    continue;
+                };
+                if location.archetype != *archetype_id || location.index != index {
+                    continue;
+                }
+                let changed = match archetype.changed_tick_raw(index, &typ) {
+                    Some(changed) => changed > self.last_tick,
+                    None => true,
+                };
+                if !changed && self.grid.contains(&entity) && !self.stale.contains(&entity) {
+                    continue;
+                }
+                match archetype.get_comp::<P>(index) {
+                    Ok(Some(position)) => {
+                        let position = position.as_ref().position();
+                        self.stale.remove(&entity);
+                        self.grid.insert(entity, position);
+                    }
+                    _ => {
+                        self.stale.insert(entity);
+                    }
+                }
+            }
+        }
+        self.last_tick = tick.saturating_sub(1);
+        removed
+    }
+    /// The Entities within `radius` of `center`.
+    pub fn query_radius(&self, center: [f32; 3], radius: f32) -> Vec<Entity> {
+        query_radius(&self.grid, center, radius)
+    }
+    /// The Entities inside the box from `min` to `max`.
+    pub fn query_aabb(&self, min: [f32; 3], max: [f32; 3]) -> Vec<Entity> {
+        query_aabb(&self.grid, min, max)
+    }
+    /// The `k` Entities closest to `center`. Closest first.
+    pub fn nearest_k(&self, center: [f32; 3], k: usize) -> Vec<Entity> {
+        self.grid.nearest_k(center, k)
+    }
+}
+
+fn query_radius(grid: &UniformGrid, center: [f32; 3], radius: f32) -> Vec<Entity> {
+    let mut entities = Vec::new();
+    grid.for_each_in_radius(center, radius, |entity, _| entities.push(entity.clone()));
+    entities
+}
+
+fn query_aabb(grid: &UniformGrid, min: [f32; 3], max: [f32; 3]) -> Vec<Entity> {
+    let mut entities = Vec::new();
+    grid.for_each_in_aabb(min, max, |entity, _| entities.push(entity.clone()));
+    entities
+}
+
+/// A [SpatialIndex] without its Component type so the World can hold it.
+pub(crate) trait DynSpatialIndex: Send + Sync + Debug {
+    fn sync(&mut self, world: &World) -> Vec<Entity>;
+    fn grid(&self) -> &UniformGrid;
+}
+
+impl<P: Spatial> DynSpatialIndex for SpatialIndex<P> {
+    fn sync(&mut self, world: &World) -> Vec<Entity> {
+        SpatialIndex::sync(self, world)
+    }
+    fn grid(&self) -> &UniformGrid {
+        &self.grid
+    }
+}
+
+impl World {
+    /// Indexes every Entity with the Component `P` by its position. Replaces the current spatial index.
+    ///
+    /// The index is filled on the next [World::sync_spatial_index].
+    pub fn set_spatial_index<P: Spatial>(&mut self, cell_size: f32) {
+        self.spatial = Some(Arc::new(RwLock::new(SpatialIndex::<P>::new(cell_size))));
+    }
+    pub fn remove_spatial_index(&mut self) {
+        self.spatial = None;
+    }
+    /// Reads the positions that changed since the last sync. Call it once per tick before querying.
+    ///
+    /// Does not advance the change tick, see [World::increment_change_tick].
+    ///
+    /// # Returns
+    /// The Entities that were removed from the index because they no longer exist.
+    pub fn sync_spatial_index(&self) -> Result<Vec<Entity>, WorldError> {
+        let index = self
+            .spatial
+            .as_ref()
+            .ok_or(WorldError::SpatialIndexNotFound)?;
+        let mut index = index.write().unwrap();
+        Ok(index.sync(self))
+    }
+    /// The Entities within `radius` of `center` as of the last sync.
+    pub fn query_radius(&self, center: [f32; 3], radius: f32) -> Result<Vec<Entity>, WorldError> {
+        self.with_spatial_index(|grid| query_radius(grid, center, radius))
+    }
+    /// The Entities inside the box from `min` to `max` as of the last sync.
+    pub fn query_aabb(&self, min: [f32; 3], max: [f32; 3]) -> Result<Vec<Entity>, WorldError> {
+        self.with_spatial_index(|grid| query_aabb(grid, min, max))
+    }
+    /// The `k` Entities closest to `center` as of the last sync. Closest first.
+    pub fn nearest_k(&self, center: [f32; 3], k: usize) -> Result<Vec<Entity>, WorldError> {
+        self.with_spatial_index(|grid| grid.nearest_k(center, k))
+    }
+
+    fn with_spatial_index<R>(&self, f: impl FnOnce(&UniformGrid) -> R) -> Result<R, WorldError> {
+        let index = self
+            .spatial
+            .as_ref()
+            .ok_or(WorldError::SpatialIndexNotFound)?;
+        let index = index.read().unwrap();
+        Ok(f(index.grid()))
+    }
+}
@@ -0,0 +1,81 @@
+//! Memory and occupancy statistics for a [World], meant to be exported to dashboards.
+use crate::world::World;
+
+/// Returned by [World::stats]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WorldStats {
+    pub archetypes: Vec<ArchetypeStats>,
+    pub entities: EntityTableStats,
+}
+
+impl WorldStats {
+    /// The bytes allocated by every Archetype.
+    pub fn bytes_allocated(&self) -> usize {
+        self.archetypes.iter().map(|a| a.bytes_allocated).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArchetypeStats {
+    pub id: u32,
+    /// The type names of the components. See [crate::archetypes::ComponentInfo::name]
+    pub components: Vec<&'static str>,
+    pub capacity: usize,
+    pub live: usize,
+    pub free_list: usize,
+    pub bytes_allocated: usize,
+    pub dense: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EntityTableStats {
+    pub capacity: u32,
+    /// IDs that have been handed out at least once.
+    pub allocated: u32,
+    pub in_use: u32,
+    pub free_list: usize,
+}
+
+impl EntityTableStats {
+    /// The fraction of the entity table in use, between 0 and 1.
+    pub fn occupancy(&self) -> f32 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        self.in_use as f32 / self.capacity as f32
+    }
+}
+
+impl World {
+    /// Collects the statistics of every Archetype and the entity table.
+    ///
+    /// Takes the free list locks briefly. The numbers can be stale if entities are added at the same time.
+    pub fn stats(&self) -> WorldStats {
+        let archetypes = self
+            .archetypes
+            .iter()
+            .map(|(id, archetype)| ArchetypeStats {
+                id: *id,
+                components: archetype.components().iter().map(|c| c.name()).collect(),
+                capacity: archetype.capacity(),
+                live: archetype.len(),
+                free_list: archetype.free_list_len(),
+                bytes_allocated: archetype.bytes_allocated(),
+                dense: archetype.is_dense(),
+            })
+            .collect();
+        let entities = &self.entities;
+        WorldStats {
+            archetypes,
+            entities: EntityTableStats {
+                capacity: entities.capacity(),
+                allocated: entities.allocated(),
+                in_use: entities.in_use(),
+                free_list: entities.0.free_list().len(),
+            },
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Changing components on several entities as one step.
+//!
+//! ```no_run, rust, ignore
+//! world.transaction(&[attacker, target], |tx| {
+//!     let damage = tx.get::<Attack>(&attacker).ok_or(NoAttack)?.damage;
+//!     tx.get_mut::<Health>(&target).ok_or(NoHealth)?.health -= damage;
+//!     tx.get_mut::<Health>(&attacker).ok_or(NoHealth)?.health += damage / 2.0;
+//!     Ok(())
+//! })?;
+//! ```
+use crate::archetypes::entity_lock::EntityGuard;
+use crate::component::Component;
+use crate::entities::entity::Entity;
+use crate::world::World;
+use std::any::TypeId;
+use std::fmt::{Debug, Display, Formatter};
+
+/// Returned by [World::transaction]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TransactionError<E> {
+    /// An entity is borrowed or locked elsewhere. Nothing was changed.
+    Conflict,
+    /// An entity was removed or never allocated. Nothing was changed.
+    EntityNotFound,
+    /// The closure returned an error. Every change made through the transaction was rolled back.
+    Aborted(E),
+}
+
+impl<E: Display> Display for TransactionError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::Conflict => write!(f, "An entity in the transaction is borrowed"),
+            TransactionError::EntityNotFound => write!(f, "Entity not found"),
+            TransactionError::Aborted(error) => write!(f, "Transaction aborted: {}", error),
+        }
+    }
+}
+
+impl<E: Debug + Display> std::error::Error for TransactionError<E> {}
+
+type Undo<'w> = (u32, TypeId, Box<dyn FnOnce() + 'w>);
+
+/// Exclusive access to the entities of a [World::transaction]
+///
+/// Rolls back every change made through [Transaction::get_mut] when dropped without being committed,
+/// which also covers a panicking closure.
+pub struct Transaction<'w> {
+    /// The guard of each entity, None for entities without components.
+    guards: Vec<(Entity, Option<EntityGuard<'w>>)>,
+    /// The pre-image of every component handed out mutably, in the order they were first changed.
+    undo: Vec<Undo<'w>>,
+    committed: bool,
+}
+
+impl World {
+    /// Locks every entity with [crate::archetypes::arche::Archetype::lock_entity] and runs `f` on them.
+    ///
+    /// Entities are locked in order of their location, so two transactions never wait on each other.
+    /// If `f` returns an error every change is rolled back.
+    ///
+    /// # Errors
+    /// [TransactionError::Conflict] if an entity is borrowed. Nothing is locked or changed in that case.
+    pub fn transaction<R, E, F>(&self, entities: &[Entity], f: F) -> Result<R, TransactionError<E>>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<R, E>,
+    {
+        let mut located = Vec::with_capacity(entities.len());
+        for entity in entities {
+            match self.entities.get_entity(entity.id) {
+                Some((current, location)) if current == *entity => {
+                    located.push((entity.clone(), location))
+                }
+                _ => return Err(TransactionError::EntityNotFound),
+            }
+        }
+        located.sort_unstable_by_key(|(entity, location)| {
+            (location.as_ref().map(|l| (l.archetype, l.index)), entity.id)
+        });
+        located.dedup_by_key(|(entity, _)| entity.id);
+
+        let mut tx = Transaction {
+            guards: Vec::with_capacity(located.len()),
+            undo: Vec::new(),
+            committed: false,
+        };
+        for (entity, location) in located {
+            let Some(location) = location else {
+                tx.guards.push((entity, None));
+                continue;
+            };
+            let archetype = self
+                .archetypes
+                .get(&location.archetype)
+                .ok_or(TransactionError::EntityNotFound)?;
+            let guard = match archetype.lock_entity(location.index) {
+                Ok(Some(guard)) => guard,
+                Ok(None) => return Err(TransactionError::EntityNotFound),
+                Err(()) => return Err(TransactionError::Conflict),
+            };
+            // A dense archetype may have moved another entity into the slot
+            if guard.entity_id() != entity.id {
+                return Err(TransactionError::Conflict);
+            }
+            tx.guards.push((entity, Some(guard)));
+        }
+
+        let result = f(&mut tx).map_err(TransactionError::Aborted)?;
+        tx.committed = true;
+        Ok(result)
+    }
+}
+
+impl<'w> Transaction<'w> {
+    pub fn get<C: Component>(&self, entity: &Entity) -> Option<&C> {
+        self.guards
+            .iter()
+            .find(|(e, _)| e == entity)?
+            .1
+            .as_ref()?
+            .get::<C>()
+    }
+    /// Keeps a clone of the Component the first time it is changed, so it can be restored on rollback.
+    ///
+    /// # Returns
+    /// None if the entity is not part of the transaction or does not have the Component.
+    pub fn get_mut<C: Component + Clone>(&mut self, entity: &Entity) -> Option<&mut C> {
+        let guard = self
+            .guards
+            .iter_mut()
+            .find(|(e, _)| e == entity)?
+            .1
+            .as_mut()?;
+        let value = guard.get_mut::<C>()?;
+        let type_id = TypeId::of::<C>();
+        if !self
+            .undo
+            .iter()
+            .any(|(id, typ, _)| *id == entity.id && *typ == type_id)
+        {
+            let old = value.clone();
+            let ptr = value as *mut C;
+            // The guard keeps the component in place until the transaction is dropped
+            self.undo
+                .push((entity.id, type_id, Box::new(move || unsafe { *ptr = old })));
+        }
+        Some(value)
+    }
+    /// The entities in the transaction, in the order they were locked.
+    pub fn entities(&self) -> impl Iterator<Item = &Entity> {
+        self.guards.iter().map(|(entity, _)| entity)
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        for (_, _, undo) in self.undo.drain(..).rev() {
+            undo();
+        }
+    }
+}
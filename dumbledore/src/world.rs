@@ -1,16 +1,43 @@
-use crate::archetypes::arche::{Archetype, ArchetypeInner};
+use crate::archetypes::arche::{Archetype, ArchetypeInner, RowRemoval};
+use crate::archetypes::borrow::BorrowPolicy;
+use crate::archetypes::ComponentInfo;
 use crate::component::{Bundle, ComponentLookup};
 use crate::entities::entity::{Entity, EntityLocation};
 use crate::entities::entity_set::{EntitySet, EntitySetInner};
+use crate::spatial::DynSpatialIndex;
 use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
 
-use std::sync::{atomic, Arc};
+use std::any::Any;
+use std::sync::atomic::AtomicU32;
+use std::sync::{atomic, Arc, Mutex, RwLock};
 
 /// The World is the central access point to the data in ECS environment.
 #[derive(Clone, Debug)]
 pub struct World {
-    archetypes: BTreeMap<u32, Archetype>,
-    entities: EntitySet,
+    pub(crate) archetypes: BTreeMap<u32, Archetype>,
+    pub(crate) entities: EntitySet,
+    /// Shared with every Archetype so borrows can record when a component changed.
+    pub(crate) change_tick: Arc<AtomicU32>,
+    /// Set with [World::set_spatial_index]
+    pub(crate) spatial: Option<Arc<RwLock<dyn DynSpatialIndex>>>,
+    /// Bundles waiting for [World::flush]
+    pub(crate) pending: PendingSpawns,
+    /// Given to every Archetype. Set with [World::set_borrow_policy]
+    pub(crate) borrow_policy: BorrowPolicy,
+}
+
+type PendingSpawn = Box<dyn FnOnce(&mut World) -> Result<(), WorldError> + Send>;
+
+/// Bundles given to [World::spawn_reserved] that are placed on the next [World::flush].
+#[derive(Clone, Default)]
+pub(crate) struct PendingSpawns(Arc<Mutex<Vec<(Entity, PendingSpawn)>>>);
+
+impl Debug for PendingSpawns {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let len = self.0.lock().unwrap().len();
+        f.debug_struct("PendingSpawns").field("len", &len).finish()
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -21,24 +48,90 @@ pub enum WorldError {
     TooManyEntitiesInWorld,
     /// The Archetype needs to be reallocated
     TooManyEntitiesInArchetype,
-    /// The reference to the world is now invalid. You will need to go get a new reference to the world
-    EntitySetLocked,
+    /// The Archetype is still referenced elsewhere and could not be reallocated
+    ArchetypeInUse,
+    /// The components given do not match the components of the Archetype
+    ArchetypeMismatch,
+    /// No spatial index was set with World::set_spatial_index
+    SpatialIndexNotFound,
+    /// The Entity was removed or never allocated
+    EntityNotFound,
+    /// The Entity already has components
+    EntityNotEmpty,
+    /// A component is borrowed in a way that conflicts with the request
+    ComponentBorrowed,
+    /// The location of the Entity is past the end of its Archetype
+    IndexOutOfRange,
+    /// The same Component was requested more than once
+    DuplicateComponent,
 }
 
+impl Display for WorldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldError::ArchetypeNotFound => write!(f, "Archetype not found"),
+            WorldError::TooManyEntitiesInWorld => write!(f, "Too many entities in the world"),
+            WorldError::TooManyEntitiesInArchetype => {
+                write!(f, "Too many entities in the archetype")
+            }
+            WorldError::ArchetypeInUse => write!(f, "Archetype is still in use"),
+            WorldError::ArchetypeMismatch => {
+                write!(f, "Components do not match the archetype")
+            }
+            WorldError::SpatialIndexNotFound => write!(f, "No spatial index"),
+            WorldError::EntityNotFound => write!(f, "Entity not found"),
+            WorldError::EntityNotEmpty => write!(f, "Entity already has components"),
+            WorldError::ComponentBorrowed => write!(f, "Component is borrowed"),
+            WorldError::IndexOutOfRange => write!(f, "Index is out of range of the archetype"),
+            WorldError::DuplicateComponent => write!(f, "Component is requested more than once"),
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
 impl World {
     /// Create a new World pre-allocated with the given number of Entities.
     pub fn new(entity_size: u32) -> Self {
         World {
             archetypes: BTreeMap::new(),
             entities: EntitySet(Arc::new(EntitySetInner::new(entity_size))),
+            change_tick: Arc::new(AtomicU32::new(1)),
+            spatial: None,
+            pending: PendingSpawns::default(),
+            borrow_policy: BorrowPolicy::default(),
         }
     }
+    /// Sets who gets a component when readers and writers compete for it, for every current and future Archetype.
+    pub fn set_borrow_policy(&mut self, policy: BorrowPolicy) {
+        self.borrow_policy = policy;
+        for archetype in self.archetypes.values() {
+            archetype.set_borrow_policy(policy);
+        }
+    }
+    pub fn borrow_policy(&self) -> BorrowPolicy {
+        self.borrow_policy
+    }
+    fn new_archetype(&self, components: Vec<ComponentInfo>, size: usize) -> ArchetypeInner {
+        let mut inner = ArchetypeInner::new(components, size, self.change_tick.clone());
+        *inner.borrow_policy.get_mut() = self.borrow_policy as u8;
+        inner
+    }
     /// Adds a new Archetype to the World based on the given Type
     ///
     /// # Arguments
     /// * `size` - The number of Entities to allocate for the Archetype.
     pub fn add_archetype<B: Bundle>(&mut self, size: usize) {
-        let inner = ArchetypeInner::new(B::component_info(), size);
+        let inner = self.new_archetype(B::component_info(), size);
+        self.archetypes
+            .insert(B::archetype_id(), Archetype(Arc::new(inner)));
+    }
+    /// Adds a dense Archetype. Removing an entity moves the last entity into its slot instead of leaving a hole.
+    ///
+    /// See [Archetype::is_dense]
+    pub fn add_dense_archetype<B: Bundle>(&mut self, size: usize) {
+        let mut inner = self.new_archetype(B::component_info(), size);
+        inner.dense = true;
         self.archetypes
             .insert(B::archetype_id(), Archetype(Arc::new(inner)));
     }
@@ -51,50 +144,305 @@ impl World {
         self.archetypes.get(&B::archetype_id())
     }
 
+    /// Shrinks the Archetype to its live entities and updates their locations.
+    ///
+    /// # Returns
+    /// The number of bytes reclaimed.
+    pub fn shrink_archetype<B: Bundle>(&mut self) -> Result<usize, WorldError> {
+        self.shrink_archetype_by_id(B::archetype_id())
+    }
+    /// Shrinks every Archetype to its live entities. Archetypes that are in use are skipped.
+    ///
+    /// Meant to be called during low load, after the entities from a peak have been removed.
+    ///
+    /// # Returns
+    /// The number of bytes reclaimed.
+    pub fn shrink_to_fit(&mut self) -> usize {
+        let ids = self.archetypes.keys().copied().collect::<Vec<_>>();
+        ids.into_iter()
+            .filter_map(|id| self.shrink_archetype_by_id(id).ok())
+            .sum()
+    }
+    fn shrink_archetype_by_id(&mut self, archetype_id: u32) -> Result<usize, WorldError> {
+        let archetype = self
+            .archetypes
+            .remove(&archetype_id)
+            .ok_or(WorldError::ArchetypeNotFound)?;
+        match archetype.shrink() {
+            Ok((archetype, report)) => {
+                self.archetypes.insert(archetype_id, archetype);
+                for (id, index) in report.moved {
+                    if let Some((entity, _)) = self.entities.get_entity(id) {
+                        self.entities.push_location(
+                            &entity,
+                            EntityLocation {
+                                archetype: archetype_id,
+                                index,
+                            },
+                        );
+                    }
+                }
+                Ok(report.bytes_reclaimed)
+            }
+            Err(archetype) => {
+                self.archetypes.insert(archetype_id, archetype);
+                Err(WorldError::ArchetypeInUse)
+            }
+        }
+    }
     /// Pushes an Archetype to the World.
     ///
     /// This us done after the Archetype has been reallocated.
     pub fn push_archetype<B: Bundle>(&mut self, archetype: Archetype) {
         self.archetypes.insert(B::archetype_id(), archetype);
     }
-    /// Increases the amount of entities in the world copying the old information.
-    ///
-    /// The old Arc is still valid, however, will no accept updates or return data.
+    /// Increases the amount of entities in the world. Defaults to doubling the capacity.
     ///
-    /// It is also marked at locked preventing updates to it
+    /// The entity table grows in place, so clones of the EntitySet stay valid and see the new capacity.
     pub fn increase_entities(&mut self, increase: Option<u32>) -> Result<(), WorldError> {
-        self.entities
-            .0
-            .locked
-            .store(true, atomic::Ordering::Relaxed);
-        let inner = self
-            .entities
-            .0
-            .reallocate(increase.unwrap_or(self.entities.0.entities.len() as u32));
-        self.entities = EntitySet(Arc::new(inner));
-        Ok(())
+        let increase = increase.unwrap_or(self.entities.capacity().max(1));
+        if self.entities.grow(increase) {
+            Ok(())
+        } else {
+            Err(WorldError::TooManyEntitiesInWorld)
+        }
     }
     pub fn get_entities(&self) -> &EntitySet {
         &self.entities
     }
+    /// The current change tick.
+    ///
+    /// Components borrowed mutably are marked with the tick at the time of the borrow.
+    pub fn change_tick(&self) -> u32 {
+        self.change_tick.load(atomic::Ordering::Relaxed)
+    }
+    /// Advances the change tick. Returns the previous tick.
+    ///
+    /// Every change made before this call has a tick less than or equal to the returned one.
+    pub fn increment_change_tick(&self) -> u32 {
+        self.change_tick.fetch_add(1, atomic::Ordering::Relaxed)
+    }
     /// Remove an entity from the world.
     /// # Arguments
     /// * `entity` - The entity to remove.
-    pub fn remove_entity<E: Into<u32>>(&mut self, entity: E) {
-        let option = self.entities.free(entity);
-        if let Some(index) = option {
-            let x = self.archetypes.get(&index.archetype).unwrap();
-            if x.remove(index.index).is_err() {
-                panic!("Tried to remove an entity that was not in the archetype");
+    ///
+    /// # Errors
+    /// [WorldError::EntityNotFound] if the entity is not in the world.
+    /// [WorldError::ComponentBorrowed] if one of its components is borrowed. The World is left as it was.
+    /// [WorldError::ArchetypeNotFound] if its Archetype was taken with [World::take_archetype].
+    pub fn remove_entity<E: Into<u32>>(&mut self, entity: E) -> Result<(), WorldError> {
+        let id = entity.into();
+        let (_, location) = self
+            .entities
+            .get_entity(id)
+            .ok_or(WorldError::EntityNotFound)?;
+        // Entities without components are not in an archetype
+        let removal = match location {
+            Some(location) => {
+                let removal = self
+                    .archetypes
+                    .get(&location.archetype)
+                    .ok_or(WorldError::ArchetypeNotFound)?
+                    .lock_for_removal(&[location.index])?;
+                Some((location.archetype, removal))
             }
-        } else {
-            panic!("Tried to remove an entity that was not in the world");
+            None => None,
+        };
+        self.entities.free(id);
+        if let Some((archetype, removal)) = removal {
+            self.apply_removal(archetype, removal);
         }
+        Ok(())
     }
-    pub fn add_entity<B: Bundle>(&self, bundle: B) -> Result<(Entity, EntityLocation), WorldError> {
-        if self.entities.is_locked() {
-            return Err(WorldError::EntitySetLocked);
+    /// Removes the locked slots from their Archetype. In a dense Archetype the locations of the moved entities are updated.
+    fn apply_removal(&self, archetype: u32, removal: RowRemoval) {
+        for (moved, index) in removal.apply() {
+            if let Some((moved, _)) = self.entities.get_entity(moved) {
+                self.entities
+                    .push_location(&moved, EntityLocation { archetype, index });
+            }
+        }
+    }
+    /// Spawns every bundle in the iterator.
+    ///
+    /// Capacity for the entities and the Archetype is reserved once up front, so this is much faster than calling
+    /// [World::add_entity] in a loop.
+    pub fn spawn_batch<B: Bundle, I: IntoIterator<Item = B>>(
+        &mut self,
+        bundles: I,
+    ) -> Result<Vec<Entity>, WorldError> {
+        let bundles = bundles.into_iter().collect::<Vec<_>>();
+        let mut components = B::component_info();
+        components.sort_unstable_by_key(|c| c.id);
+        self.ensure_entity_capacity(bundles.len())?;
+        self.ensure_archetype(B::archetype_id(), &components, bundles.len())?;
+
+        let archetype = &self.archetypes[&B::archetype_id()];
+        let slots = archetype.alloc_slots(bundles.len());
+        let mut entities = Vec::with_capacity(bundles.len());
+        for (bundle, index) in bundles.into_iter().zip(slots) {
+            let entity = self.entities.alloc();
+            let ptr = archetype.init_slot(index, entity.id);
+            unsafe {
+                bundle.put_self(|data, info| archetype.write_component(ptr, data, &info));
+            }
+            self.entities.push_location(
+                &entity,
+                EntityLocation {
+                    archetype: B::archetype_id(),
+                    index,
+                },
+            );
+            entities.push(entity);
         }
+        Ok(entities)
+    }
+    /// Removes every Entity in the slice. Entities that were already removed are skipped.
+    ///
+    /// Every entity is locked before anything is removed, so either all of them are removed or none.
+    ///
+    /// # Returns
+    /// The number of entities removed.
+    ///
+    /// # Errors
+    /// [WorldError::ComponentBorrowed] if one of the entities is borrowed, or one that a dense Archetype would move
+    /// into a freed slot. [WorldError::ArchetypeNotFound] if the Archetype of one of them was taken and
+    /// [WorldError::IndexOutOfRange] if one of them is past the end of its Archetype. Nothing is removed in any case.
+    pub fn despawn_batch(&mut self, entities: &[Entity]) -> Result<usize, WorldError> {
+        let mut ids = Vec::with_capacity(entities.len());
+        let mut by_archetype: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for entity in entities {
+            let location = match self.entities.get_entity(entity.id) {
+                Some((current, location)) if current == *entity => location,
+                _ => continue,
+            };
+            ids.push(entity.id);
+            if let Some(location) = location {
+                by_archetype
+                    .entry(location.archetype)
+                    .or_default()
+                    .push(location.index);
+            }
+        }
+        // Listing an entity twice only removes it once
+        ids.sort_unstable();
+        ids.dedup();
+        let mut removals = Vec::with_capacity(by_archetype.len());
+        for (archetype, indices) in by_archetype {
+            let removal = self
+                .archetypes
+                .get(&archetype)
+                .ok_or(WorldError::ArchetypeNotFound)?
+                .lock_for_removal(&indices)?;
+            removals.push((archetype, removal));
+        }
+        for id in ids.iter() {
+            self.entities.free(*id);
+        }
+        for (archetype, removal) in removals {
+            self.apply_removal(archetype, removal);
+        }
+        Ok(ids.len())
+    }
+    /// Returns true if the Entity has no components.
+    pub fn is_empty(&self, entity: &Entity) -> Result<bool, WorldError> {
+        match self.entities.get_entity(entity.id) {
+            Some((current, location)) if current == *entity => Ok(location.is_none()),
+            _ => Err(WorldError::EntityNotFound),
+        }
+    }
+    /// Reads Components of an Entity, such as `get::<(Position, Health)>(&entity)`, without naming its Bundle.
+    ///
+    /// # Returns
+    /// Ok(None) if the Entity does not have every Component.
+    ///
+    /// # Errors
+    /// [WorldError::EntityNotFound] if the Entity was removed or never allocated.
+    /// [WorldError::ComponentBorrowed] if a Component is mutably borrowed or the Entity is locked.
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub fn get<'w, T: ComponentLookup<'w>>(
+        &'w self,
+        entity: &Entity,
+    ) -> Result<Option<T::RefResponse>, WorldError> {
+        self.entity(entity)
+            .ok_or(WorldError::EntityNotFound)?
+            .get::<T>()
+    }
+    /// Like [World::get], but the Components are borrowed mutably and marked as changed at the current change tick.
+    ///
+    /// # Errors
+    /// [WorldError::EntityNotFound] if the Entity was removed or never allocated.
+    /// [WorldError::ComponentBorrowed] if a Component is borrowed or the Entity is locked.
+    /// [WorldError::DuplicateComponent] if a Component is listed twice.
+    #[cfg_attr(feature = "debug-borrows", track_caller)]
+    pub fn get_mut<'w, T: ComponentLookup<'w>>(
+        &'w self,
+        entity: &Entity,
+    ) -> Result<Option<T::MutResponse>, WorldError> {
+        self.entity(entity)
+            .ok_or(WorldError::EntityNotFound)?
+            .get_mut::<T>()
+    }
+    /// Gives the components in the bundle to an Entity without components.
+    ///
+    /// The Archetype is created or grown if needed.
+    pub fn insert<B: Bundle>(
+        &mut self,
+        entity: &Entity,
+        bundle: B,
+    ) -> Result<EntityLocation, WorldError> {
+        if !self.is_empty(entity)? {
+            return Err(WorldError::EntityNotEmpty);
+        }
+        let mut components = B::component_info();
+        components.sort_unstable_by_key(|c| c.id);
+        self.ensure_archetype(B::archetype_id(), &components, 1)?;
+        let archetype = &self.archetypes[&B::archetype_id()];
+        let location = EntityLocation {
+            archetype: B::archetype_id(),
+            index: archetype.add_entity(entity.id, bundle),
+        };
+        self.entities.push_location(entity, location.clone());
+        Ok(location)
+    }
+    /// Reserves an Entity without placing it in an Archetype.
+    ///
+    /// Does not need a lock, so it can be called from any thread holding a reference to the World.
+    /// The Entity has no components until it is given a bundle with [World::insert] or [World::spawn_reserved].
+    pub fn reserve_entity(&self) -> Entity {
+        self.entities.reserve()
+    }
+    /// Reserves `count` Entities. See [World::reserve_entity]
+    pub fn reserve_entities(&self, count: u32) -> Vec<Entity> {
+        self.entities.reserve_many(count)
+    }
+    /// Queues the bundle to be placed on a reserved Entity during the next [World::flush].
+    pub fn spawn_reserved<B: Bundle + Send + 'static>(&self, entity: &Entity, bundle: B) {
+        let target = entity.clone();
+        let spawn: PendingSpawn =
+            Box::new(move |world: &mut World| world.insert(&target, bundle).map(|_| ()));
+        self.pending.0.lock().unwrap().push((entity.clone(), spawn));
+    }
+    /// Places the bundles queued with [World::spawn_reserved].
+    ///
+    /// Reserved entities without a bundle stay empty. Bundles for entities that were removed or already placed are dropped.
+    ///
+    /// # Returns
+    /// The entities that could not be placed. They stay alive without components.
+    pub fn flush(&mut self) -> Vec<(Entity, WorldError)> {
+        let pending = std::mem::take(&mut *self.pending.0.lock().unwrap());
+        let mut failed = Vec::new();
+        for (entity, spawn) in pending {
+            if self.is_empty(&entity) != Ok(true) {
+                continue;
+            }
+            if let Err(error) = spawn(self) {
+                failed.push((entity, error));
+            }
+        }
+        failed
+    }
+    pub fn add_entity<B: Bundle>(&self, bundle: B) -> Result<(Entity, EntityLocation), WorldError> {
         if !self.entities.entities_left() {
             return Err(WorldError::TooManyEntitiesInWorld);
         }
@@ -117,4 +465,79 @@ impl World {
         let location = self.entities.get_location(entity.id).unwrap();
         Ok((entity, location))
     }
+
+    /// Makes sure an Archetype with exactly these components exists and has room for `count` more entities.
+    ///
+    /// `components` must be sorted by id.
+    pub(crate) fn ensure_archetype(
+        &mut self,
+        archetype_id: u32,
+        components: &[ComponentInfo],
+        count: usize,
+    ) -> Result<(), WorldError> {
+        let Some(archetype) = self.archetypes.get(&archetype_id) else {
+            let inner = self.new_archetype(components.to_vec(), count);
+            self.archetypes
+                .insert(archetype_id, Archetype(Arc::new(inner)));
+            return Ok(());
+        };
+        if archetype.0.components.as_ref() != components {
+            return Err(WorldError::ArchetypeMismatch);
+        }
+        let used = archetype.0.entities_len.load(atomic::Ordering::Relaxed) as usize;
+        if used + count <= archetype.0.entity_data.len() {
+            return Ok(());
+        }
+        // Grow by at least the current size so repeated calls do not reallocate every time
+        let needed =
+            (used + count - archetype.0.entity_data.len()).max(archetype.0.entity_data.len());
+        let archetype = self.archetypes.remove(&archetype_id).unwrap();
+        match archetype.resize(Some(needed)) {
+            Ok(archetype) => {
+                self.archetypes.insert(archetype_id, archetype);
+                Ok(())
+            }
+            Err(archetype) => {
+                self.archetypes.insert(archetype_id, archetype);
+                Err(WorldError::ArchetypeInUse)
+            }
+        }
+    }
+    /// Makes sure `count` more entities can be allocated.
+    pub(crate) fn ensure_entity_capacity(&mut self, count: usize) -> Result<(), WorldError> {
+        let used = self.entities.0.length.load(atomic::Ordering::Relaxed) as usize;
+        let capacity = self.entities.capacity() as usize;
+        if used + count > capacity {
+            let needed = used + count - capacity;
+            self.increase_entities(Some(needed as u32))?;
+        }
+        Ok(())
+    }
+    /// Moves boxed components into the Archetype and records the location of the entity.
+    ///
+    /// Room has to be made with [World::ensure_archetype] first.
+    pub(crate) fn add_entity_boxed(
+        &self,
+        entity: &Entity,
+        archetype_id: u32,
+        components: Vec<(ComponentInfo, Box<dyn Any + Send + Sync>)>,
+    ) -> EntityLocation {
+        let archetype = &self.archetypes[&archetype_id];
+        let (index, ptr) = archetype.alloc_slot(entity.id);
+        for (info, value) in components {
+            let data = Box::into_raw(value) as *mut u8;
+            unsafe {
+                archetype.write_component(ptr, data, &info);
+                if info.layout.size() != 0 {
+                    std::alloc::dealloc(data, info.layout);
+                }
+            }
+        }
+        let location = EntityLocation {
+            archetype: archetype_id,
+            index,
+        };
+        self.entities.push_location(entity, location.clone());
+        location
+    }
 }
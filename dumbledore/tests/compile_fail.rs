@@ -0,0 +1,6 @@
+//! Borrows handed out by an Archetype can not outlive it.
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/compile_fail/*.rs");
+}
@@ -0,0 +1,22 @@
+use dumbledore::component::Component;
+use dumbledore::{Bundle, Component};
+use dumbledore::world::World;
+
+#[derive(Component)]
+struct Position {
+    x: f32,
+}
+
+#[derive(Bundle)]
+struct Player {
+    position: Position,
+}
+
+fn main() {
+    let mut world = World::new(16);
+    let archetype = world.take_archetype::<Player>().unwrap();
+    let position = archetype.get_comp_mut::<Position>(0).unwrap().unwrap();
+    let archetype = archetype.resize(None).unwrap();
+    drop(position);
+    drop(archetype);
+}
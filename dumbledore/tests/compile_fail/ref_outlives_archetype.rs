@@ -0,0 +1,21 @@
+use dumbledore::component::Component;
+use dumbledore::{Bundle, Component};
+use dumbledore::component_ref::ComponentRef;
+use dumbledore::world::World;
+
+#[derive(Component)]
+struct Position {
+    x: f32,
+}
+
+#[derive(Bundle)]
+struct Player {
+    position: Position,
+}
+
+fn dangling(world: &World, index: u32) -> ComponentRef<'static, Position> {
+    let archetype = world.get_archetype::<Player>().unwrap().clone();
+    archetype.get_comp::<Position>(index).unwrap().unwrap()
+}
+
+fn main() {}
@@ -0,0 +1,25 @@
+use dumbledore::component::Component;
+use dumbledore::{Bundle, Component};
+use dumbledore::world::World;
+
+#[derive(Component)]
+struct Position {
+    x: f32,
+}
+
+#[derive(Bundle)]
+struct Player {
+    position: Position,
+}
+
+fn main() {
+    let world = World::new(16);
+    let position = world
+        .get_archetype::<Player>()
+        .unwrap()
+        .get_comp::<Position>(0)
+        .unwrap()
+        .unwrap();
+    drop(world);
+    drop(position);
+}
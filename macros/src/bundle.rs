@@ -68,9 +68,8 @@ pub(crate) fn process_bundle(input: DeriveInput, en: DataStruct) -> Result<Token
                 components.push(field.ty.clone());
 
                 comp_refs.push(quote! {
-                    let #ident = &mut self.#ident as *mut #typ;
-                    f(#ident.cast(),dumbledore::archetypes::ComponentInfo::new::<#typ>());
-                    std::mem::forget(self.#ident);
+                    let mut #ident = std::mem::ManuallyDrop::new(self.#ident);
+                    f((&mut *#ident as *mut #typ).cast(),dumbledore::archetypes::ComponentInfo::new::<#typ>());
                 });
             });
         }